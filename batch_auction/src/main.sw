@@ -0,0 +1,511 @@
+contract;
+
+use ns_lib::{abs, NuclearSwap};
+
+use std::{
+    address::*,
+    assert::assert,
+    block::*,
+    chain::auth::*,
+    context::{*, call_frames::*},
+    contract_id::ContractId,
+    hash::*,
+    logging::log,
+    result::*,
+    revert::revert,
+    storage::*,
+    token::*,
+};
+
+storage {
+    initialized: bool,
+    pool: b256,
+    asset_0: b256,
+    asset_1: b256,
+    batch_window: u64,
+    min_order: u64,
+    next_order_id: u64,
+    batch_start: u64,
+    batch_opened_height: u64,
+}
+
+pub struct Order {
+    owner: b256,
+    asset_in: ContractId,
+    amount_in: u64,
+    min_out: u64,
+}
+
+pub struct SubmitOrderLog {
+    order_id: u64,
+    owner: b256,
+    asset_in: ContractId,
+    amount_in: u64,
+    min_out: u64,
+}
+
+pub struct RefundOrderLog {
+    order_id: u64,
+    owner: b256,
+    asset_in: ContractId,
+    amount_in: u64,
+}
+
+pub struct SettleBatchLog {
+    first_order_id: u64,
+    end_order_id: u64,
+    clearing_price: u64,
+    residual_asset: ContractId,
+    residual_in: u64,
+    residual_out: u64,
+}
+
+// What each side of a batch receives in total at the clearing price, and the part that went through the pool
+struct Clearing {
+    price: u64,
+    received_0: u64,
+    received_1: u64,
+    residual_asset: b256,
+    residual_in: u64,
+    residual_out: u64,
+}
+
+// Storage delimited
+const S_ORDER_OWNERS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000000;
+const S_ORDER_IS_CONTRACT: b256 = 0x0000000000000000000000000000000000000000000000000000000000000001;
+const S_ORDER_ASSETS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000002;
+const S_ORDER_AMOUNTS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000003;
+const S_ORDER_MIN_OUTS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000004;
+const S_ORDER_EXCLUDED: b256 = 0x0000000000000000000000000000000000000000000000000000000000000005;
+const S_ORDER_REFUNDED: b256 = 0x0000000000000000000000000000000000000000000000000000000000000006;
+const S_OWNER_ORDERS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000007;
+
+// Most orders a batch can hold, settling loops over all of them
+const MAX_BATCH_SIZE = 16;
+
+// Most orders one owner can have in a batch, so a single owner can't fill it up
+const MAX_ORDERS_PER_OWNER = 4;
+
+// Prices are expressed with 6 decimals, as in stable_swap
+const PRICE_PRECISION = 1000000;
+
+// Times the clearing price is requoted on the pool before settling at it
+const CLEARING_ROUNDS = 4;
+
+// Furthest the clearing price may be from the pool's EMA price, over PRICE_PRECISION
+const MAX_ORACLE_DEVIATION = 10000;
+
+// Blocks after a batch's window during which it can be settled. Once they have passed and the batch
+// still hasn't settled, for instance because the pool is paused, anyone can refund its orders.
+const SETTLE_TIMEOUT = 20;
+
+// Collects swaps on a stable_swap pool for `batch_window` blocks and settles them together.
+// Opposing orders are matched with each other and only what is left over on one side is swapped
+// on the pool. Every order in the batch, on either side, trades at the same price, and a batch
+// only settles while that price is close to the pool's EMA, so moving the pool just before
+// settling can't be used to skim the batch.
+abi BatchAuction {
+    fn initialize(pool: ContractId, batch_window: u64, min_order: u64);
+    fn get_pool() -> ContractId;
+    fn submit_order(asset_in: ContractId, amount_in: u64, min_out: u64) -> u64;
+    fn settle_batch() -> u64;
+    fn refund_order(order_id: u64) -> u64;
+    fn get_order(order_id: u64) -> Order;
+    fn get_pending_orders() -> u64;
+}
+
+impl BatchAuction for Contract {
+    fn initialize(pool: ContractId, batch_window: u64, min_order: u64) {
+        // Open to the first caller, so check `get_pool` before sending orders to a new auction
+        assert(storage.initialized == false);
+        assert(min_order > 0);
+
+        let assets = abi(NuclearSwap, pool.into()).get_assets();
+        storage.pool = pool.into();
+        storage.asset_0 = assets[0].into();
+        storage.asset_1 = assets[1].into();
+        storage.batch_window = batch_window;
+        storage.min_order = min_order;
+        storage.initialized = true;
+    }
+
+    fn get_pool() -> ContractId {
+        ~ContractId::from(storage.pool)
+    }
+
+    fn submit_order(asset_in: ContractId, amount_in: u64, min_out: u64) -> u64 {
+        assert(storage.initialized);
+        assert(asset_in.into() == storage.asset_0 || asset_in.into() == storage.asset_1);
+        assert(msg_asset_id().into() == asset_in.into());
+        assert(amount_in >= storage.min_order && msg_amount() == amount_in);
+
+        let order_id = storage.next_order_id;
+        assert(order_id - storage.batch_start < MAX_BATCH_SIZE);
+
+        // The first order of a batch opens its window
+        if order_id == storage.batch_start {
+            storage.batch_opened_height = height();
+        };
+
+        let sender = get_msg_sender_or_panic();
+        let mut is_contract = false;
+        if let Sender::ContractId(v) = sender {
+            is_contract = true;
+        };
+
+        let owner = sender_key(sender);
+        let owner_orders = get::<u64>(key_owner_orders(owner, storage.batch_start));
+        assert(owner_orders < MAX_ORDERS_PER_OWNER);
+        store(key_owner_orders(owner, storage.batch_start), owner_orders + 1);
+
+        store(key_order(S_ORDER_OWNERS, order_id), owner);
+        store(key_order(S_ORDER_IS_CONTRACT, order_id), is_contract);
+        store(key_order(S_ORDER_ASSETS, order_id), asset_in.into());
+        store(key_order(S_ORDER_AMOUNTS, order_id), amount_in);
+        store(key_order(S_ORDER_MIN_OUTS, order_id), min_out);
+        storage.next_order_id = order_id + 1;
+
+        log(SubmitOrderLog {
+            order_id: order_id,
+            owner: owner,
+            asset_in: asset_in,
+            amount_in: amount_in,
+            min_out: min_out,
+        });
+
+        order_id
+    }
+
+    fn settle_batch() -> u64 {
+        let first = storage.batch_start;
+        let end = storage.next_order_id;
+        assert(end > first);
+        assert(height() >= storage.batch_opened_height + storage.batch_window);
+
+        // The pool's EMA is asset 0 per asset 1, the clearing runs on its inverse, asset 1 per asset 0
+        let ma_price = abi(NuclearSwap, storage.pool).price_oracle();
+        assert(ma_price > 0);
+        let price = (PRICE_PRECISION * PRICE_PRECISION) / ma_price;
+        assert(price > 0);
+
+        // An order whose limit the clearing can't meet sits the batch out. That moves the clearing
+        // for everyone else, so repeat until it holds for all orders that are left.
+        let mut changed = true;
+        let mut rounds = 0;
+        while changed && rounds < MAX_BATCH_SIZE {
+            changed = false;
+            let totals = batch_totals(first, end);
+            let quote = clearing(price, totals, false);
+
+            let mut order_id = first;
+            while order_id < end {
+                if is_excluded(order_id) == false && payout(order_id, totals, quote) < read_order(order_id).min_out {
+                    store(key_order(S_ORDER_EXCLUDED, order_id), true);
+                    changed = true;
+                };
+                order_id = order_id + 1;
+            }
+            rounds = rounds + 1;
+        }
+
+        let totals = batch_totals(first, end);
+        let result = clearing(price, totals, true);
+        assert(abs(result.price, price) * PRICE_PRECISION <= price * MAX_ORACLE_DEVIATION);
+        storage.batch_start = end;
+
+        // Pay out pro rata. The last filled order on each side takes the rounding dust, so
+        // everything the side receives is paid out.
+        let last = [last_filled(first, end, storage.asset_0), last_filled(first, end, storage.asset_1)];
+        let mut paid_0 = 0;
+        let mut paid_1 = 0;
+        let mut filled = 0;
+        let mut order_id = first;
+        while order_id < end {
+            let order = read_order(order_id);
+            if is_excluded(order_id) && is_refunded(order_id) == false {
+                transfer_to_sender(order.amount_in, order.asset_in, order_owner(order_id));
+            } else if is_excluded(order_id) == false {
+                let mut amount_out = payout(order_id, totals, result);
+                if order.asset_in.into() == storage.asset_0 {
+                    if order_id == last[0] {
+                        amount_out = result.received_0 - paid_0;
+                    };
+                    paid_0 = paid_0 + amount_out;
+                } else {
+                    if order_id == last[1] {
+                        amount_out = result.received_1 - paid_1;
+                    };
+                    paid_1 = paid_1 + amount_out;
+                };
+                assert(amount_out >= order.min_out);
+                transfer_to_sender(amount_out, ~ContractId::from(other_asset(order.asset_in.into())), order_owner(order_id));
+                filled = filled + 1;
+            };
+            order_id = order_id + 1;
+        }
+
+        log(SettleBatchLog {
+            first_order_id: first,
+            end_order_id: end,
+            clearing_price: result.price,
+            residual_asset: ~ContractId::from(result.residual_asset),
+            residual_in: result.residual_in,
+            residual_out: result.residual_out,
+        });
+
+        filled
+    }
+
+    fn refund_order(order_id: u64) -> u64 {
+        assert(order_id >= storage.batch_start && order_id < storage.next_order_id);
+        assert(height() > storage.batch_opened_height + storage.batch_window + SETTLE_TIMEOUT);
+        assert(is_refunded(order_id) == false);
+
+        // The order sits out whatever settlement the batch still gets
+        store(key_order(S_ORDER_EXCLUDED, order_id), true);
+        store(key_order(S_ORDER_REFUNDED, order_id), true);
+
+        let order = read_order(order_id);
+        transfer_to_sender(order.amount_in, order.asset_in, order_owner(order_id));
+
+        log(RefundOrderLog {
+            order_id: order_id,
+            owner: order.owner,
+            asset_in: order.asset_in,
+            amount_in: order.amount_in,
+        });
+
+        order.amount_in
+    }
+
+    fn get_order(order_id: u64) -> Order {
+        read_order(order_id)
+    }
+
+    fn get_pending_orders() -> u64 {
+        storage.next_order_id - storage.batch_start
+    }
+}
+
+// Input of the orders still in the batch, by the pool's asset index
+fn batch_totals(first: u64, end: u64) -> [u64; 2] {
+    let mut total_0 = 0;
+    let mut total_1 = 0;
+    let mut order_id = first;
+    while order_id < end {
+        if is_excluded(order_id) == false {
+            let order = read_order(order_id);
+            if order.asset_in.into() == storage.asset_0 {
+                total_0 = total_0 + order.amount_in;
+            } else {
+                total_1 = total_1 + order.amount_in;
+            };
+        };
+        order_id = order_id + 1;
+    }
+
+    [total_0, total_1]
+}
+
+// Net the two sides against each other and send the leftover of the side with more input through
+// the pool. The matched part trades at the rate the pool gives the leftover, so both sides get one
+// price and share the pool's fee and price impact pro rata to their input. That rate depends on the
+// size of the leftover, so it is requoted a few times starting from `price`.
+// Prices are asset 1 per asset 0. Without `execute` the pool's output is only quoted.
+fn clearing(price: u64, totals: [u64; 2], execute: bool) -> Clearing {
+    // Side k has the leftover, `rate` is the other side's asset per unit of side k's
+    let mut k = 0;
+    let mut rate = price;
+    if (totals[0] * price) / PRICE_PRECISION < totals[1] {
+        k = 1;
+        rate = (PRICE_PRECISION * PRICE_PRECISION) / price;
+    };
+    assert(rate > 0);
+    let residual_asset = side_asset(k);
+
+    let mut residual_in = 0;
+    let mut residual_out = 0;
+    let mut round = 0;
+    while round < CLEARING_ROUNDS {
+        residual_in = leftover(totals[k], totals[1 - k], rate);
+        residual_out = swap_residual(residual_asset, residual_in, false);
+        // Keep the last rate when the pool's output is too small to give one
+        if residual_out * PRICE_PRECISION >= residual_in && residual_in > 0 {
+            rate = (residual_out * PRICE_PRECISION) / residual_in;
+        };
+        round = round + 1;
+    }
+    residual_in = leftover(totals[k], totals[1 - k], rate);
+    residual_out = swap_residual(residual_asset, residual_in, execute);
+
+    // Side k gets all of the other side's input and the pool's output, the other side gets
+    // what side k put in that didn't go to the pool
+    let mut received_0 = totals[1] + residual_out;
+    let mut received_1 = totals[0] - residual_in;
+    if k == 1 {
+        received_0 = totals[1] - residual_in;
+        received_1 = totals[0] + residual_out;
+    };
+
+    let mut clearing_price = price;
+    if totals[0] > 0 && received_0 > 0 {
+        clearing_price = (received_0 * PRICE_PRECISION) / totals[0];
+    } else if totals[1] > 0 && received_1 > 0 {
+        clearing_price = (totals[1] * PRICE_PRECISION) / received_1;
+    };
+
+    Clearing {
+        price: clearing_price,
+        received_0: received_0,
+        received_1: received_1,
+        residual_asset: residual_asset,
+        residual_in: residual_in,
+        residual_out: residual_out,
+    }
+}
+
+// What is left of `total_k` once the other side's `total_other` has been matched with it at `rate`
+fn leftover(total_k: u64, total_other: u64, rate: u64) -> u64 {
+    let matched = (total_other * PRICE_PRECISION) / rate;
+
+    let mut ret = 0;
+    if total_k > matched {
+        ret = total_k - matched;
+    };
+
+    ret
+}
+
+fn swap_residual(asset_in: b256, amount_in: u64, execute: bool) -> u64 {
+    let pool = abi(NuclearSwap, storage.pool);
+
+    let mut ret = 0;
+    if amount_in > 0 {
+        ret = pool.get_dy(~ContractId::from(asset_in), amount_in);
+    };
+    if amount_in > 0 && execute {
+        // The orders' limits were checked against the quote, so the swap must give at least that
+        // for every payout to stay above them. The pool sends the output back to the auction.
+        ret = pool.swap {
+            coins: amount_in, asset_id: asset_in
+        }(amount_in, ret, height() + 1);
+    };
+
+    ret
+}
+
+// An order's pro rata share of what its side receives
+fn payout(order_id: u64, totals: [u64; 2], result: Clearing) -> u64 {
+    let order = read_order(order_id);
+
+    let mut ret = 0;
+    if order.asset_in.into() == storage.asset_0 {
+        ret = (result.received_0 * order.amount_in) / totals[0];
+    } else {
+        ret = (result.received_1 * order.amount_in) / totals[1];
+    };
+
+    ret
+}
+
+// The highest order id on the side selling `asset_in` that is still in the batch, or `end` if there is none
+fn last_filled(first: u64, end: u64, asset_in: b256) -> u64 {
+    let mut ret = end;
+    let mut order_id = first;
+    while order_id < end {
+        if is_excluded(order_id) == false && read_order(order_id).asset_in.into() == asset_in {
+            ret = order_id;
+        };
+        order_id = order_id + 1;
+    }
+
+    ret
+}
+
+fn read_order(order_id: u64) -> Order {
+    Order {
+        owner: get::<b256>(key_order(S_ORDER_OWNERS, order_id)),
+        asset_in: ~ContractId::from(get::<b256>(key_order(S_ORDER_ASSETS, order_id))),
+        amount_in: get::<u64>(key_order(S_ORDER_AMOUNTS, order_id)),
+        min_out: get::<u64>(key_order(S_ORDER_MIN_OUTS, order_id)),
+    }
+}
+
+fn is_excluded(order_id: u64) -> bool {
+    get::<bool>(key_order(S_ORDER_EXCLUDED, order_id))
+}
+
+fn is_refunded(order_id: u64) -> bool {
+    get::<bool>(key_order(S_ORDER_REFUNDED, order_id))
+}
+
+// The order's owner as it submitted, an address or a contract
+fn order_owner(order_id: u64) -> Sender {
+    let owner = get::<b256>(key_order(S_ORDER_OWNERS, order_id));
+
+    let mut ret = Sender::Address(~Address::from(owner));
+    if get::<bool>(key_order(S_ORDER_IS_CONTRACT, order_id)) {
+        ret = Sender::ContractId(~ContractId::from(owner));
+    };
+
+    ret
+}
+
+// The asset sold by side `k` of the batch
+fn side_asset(k: u64) -> b256 {
+    let mut ret = storage.asset_0;
+    if k == 1 {
+        ret = storage.asset_1;
+    };
+
+    ret
+}
+
+fn other_asset(asset_in: b256) -> b256 {
+    let mut ret = storage.asset_1;
+    if asset_in == storage.asset_1 {
+        ret = storage.asset_0;
+    };
+
+    ret
+}
+
+// Compute the storage slot of a field of an order
+fn key_order(field: b256, order_id: u64) -> b256 {
+    sha256((field, order_id))
+}
+
+// Compute the storage slot of the number of orders an owner has in the batch starting at `batch_start`
+fn key_owner_orders(owner: b256, batch_start: u64) -> b256 {
+    sha256((S_OWNER_ORDERS, owner, batch_start))
+}
+
+// Return the sender, whether it is an address or a contract, or panic
+fn get_msg_sender_or_panic() -> Sender {
+    let result: Result<Sender, AuthError> = msg_sender();
+    if result.is_err() {
+        revert(0);
+    };
+
+    result.unwrap()
+}
+
+// Send coins to an address as an output, or straight into a contract's balance
+fn transfer_to_sender(amount: u64, asset_id: ContractId, to: Sender) {
+    if let Sender::Address(v) = to {
+        transfer_to_output(amount, asset_id, v);
+    } else if let Sender::ContractId(v) = to {
+        force_transfer(amount, asset_id, v);
+    };
+}
+
+fn sender_key(sender: Sender) -> b256 {
+    let mut ret = 0x0000000000000000000000000000000000000000000000000000000000000000;
+    if let Sender::Address(v) = sender {
+        ret = v.into();
+    } else if let Sender::ContractId(v) = sender {
+        ret = v.into();
+    };
+
+    ret
+}
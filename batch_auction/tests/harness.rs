@@ -0,0 +1,301 @@
+use fuel_tx::{AssetId, ContractId};
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::test_helpers;
+use test_utils::{get_funded_token, get_pool_with_liquidity, get_wallet_balance, launch_provider_and_get_wallets, produce_blocks};
+
+// Load abi from json
+abigen!(MyContract, "out/debug/batch_auction-abi.json");
+abigen!(StableSwap, "../stable_swap/out/debug/stable_swap-abi.json");
+
+// Three funded wallets, a balanced pool of two tokens opened by the first one, and an auction on it
+async fn get_contract_instance() -> (MyContract, ContractId, Vec<LocalWallet>, ContractId, [ContractId; 2]) {
+    // Launch a local network with three wallets and deploy the contract
+    let wallets = launch_provider_and_get_wallets(3).await;
+    let wallet = wallets[0].clone();
+
+    let token_a_id = get_funded_token(&wallets, 1, 100000).await;
+    let token_b_id = get_funded_token(&wallets, 2, 100000).await;
+    let assets = [token_a_id, token_b_id];
+
+    let pool_id = get_pool_with_liquidity(&wallet, 1, assets.clone(), 50000).await;
+
+    let id = Contract::deploy("./out/debug/batch_auction.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let instance = MyContract::new(id.to_string(), wallet.clone());
+    instance
+        .initialize(pool_id.clone(), 2, 100)
+        .set_contracts(&[pool_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    let response = instance.get_pool().call().await.unwrap();
+    assert_eq!(response.value, pool_id);
+
+    (instance, id, wallets, pool_id, assets)
+}
+
+async fn submit_order(
+    auction_id: &ContractId,
+    wallet: &LocalWallet,
+    asset_in: &ContractId,
+    amount_in: u64,
+    min_out: u64,
+) -> u64 {
+    MyContract::new(auction_id.to_string(), wallet.clone())
+        .submit_order(asset_in.clone(), amount_in, min_out)
+        .call_params(CallParameters::new(Some(amount_in), Some(AssetId::from(**asset_in))))
+        .call()
+        .await
+        .unwrap()
+        .value
+}
+
+#[tokio::test]
+async fn can_settle_crossing_orders() {
+    let (_instance, _id, wallets, pool_id, assets) = get_contract_instance().await;
+    let pool_instance = StableSwap::new(pool_id.to_string(), wallets[0].clone());
+
+    let a_before: Vec<u64> = vec![
+        get_wallet_balance(&wallets[0], &assets[0]).await,
+        get_wallet_balance(&wallets[1], &assets[0]).await,
+        get_wallet_balance(&wallets[2], &assets[0]).await,
+    ];
+    let b_before: Vec<u64> = vec![
+        get_wallet_balance(&wallets[0], &assets[1]).await,
+        get_wallet_balance(&wallets[1], &assets[1]).await,
+        get_wallet_balance(&wallets[2], &assets[1]).await,
+    ];
+
+    // Two wallets sell A, one sells B
+    let order_id = submit_order(&_id, &wallets[1], &assets[0], 3000, 1).await;
+    assert_eq!(order_id, 0);
+
+    // The batch window is still open
+    let result = _instance
+        .settle_batch()
+        .set_contracts(&[pool_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    submit_order(&_id, &wallets[2], &assets[1], 1000, 990).await;
+    submit_order(&_id, &wallets[0], &assets[0], 500, 1).await;
+    let response = _instance.get_order(1).call().await.unwrap();
+    assert_eq!(response.value.owner, *wallets[2].address());
+    assert_eq!(response.value.amount_in, 1000);
+    let response = _instance.get_pending_orders().call().await.unwrap();
+    assert_eq!(response.value, 3);
+
+    // Anyone can settle once the window has passed
+    let keeper_instance = MyContract::new(_id.to_string(), wallets[2].clone());
+    let response = keeper_instance
+        .settle_batch()
+        .set_contracts(&[pool_id.clone()])
+        .append_variable_outputs(3)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 3);
+    let response = _instance.get_pending_orders().call().await.unwrap();
+    assert_eq!(response.value, 0);
+
+    // The balanced pool trades close to 1:1, and the B seller's A comes from the A sellers
+    let b_seller_received = get_wallet_balance(&wallets[2], &assets[0]).await - a_before[2];
+    assert!(b_seller_received >= 1000);
+    assert!(b_seller_received <= 1001);
+    assert_eq!(get_wallet_balance(&wallets[2], &assets[1]).await, b_before[2] - 1000);
+
+    // Only the A nobody took went through the pool, in a single swap
+    let stats = pool_instance.get_stats().call().await.unwrap().value;
+    assert_eq!(stats.swap_count, 1);
+    assert!(stats.eth_volume >= 2499);
+    assert!(stats.eth_volume <= 2500);
+    assert_eq!(stats.token_volume, 0);
+
+    // Both A sellers get the same rate
+    let received_0 = get_wallet_balance(&wallets[0], &assets[1]).await - b_before[0];
+    let received_1 = get_wallet_balance(&wallets[1], &assets[1]).await - b_before[1];
+    assert_eq!(get_wallet_balance(&wallets[0], &assets[0]).await, a_before[0] - 500);
+    assert_eq!(get_wallet_balance(&wallets[1], &assets[0]).await, a_before[1] - 3000);
+    assert!(received_1 > 2990);
+    assert!(received_1 < 3000);
+    assert!((received_0 * 6).abs_diff(received_1) <= 6);
+
+    // And so does the B seller: A per B is the inverse of B per A, up to rounding
+    let a_sellers_received = received_0 + received_1;
+    assert!((a_sellers_received * b_seller_received).abs_diff(3500 * 1000) <= 3500);
+
+    // Everything the sides received was paid out, no dust is left in the auction
+    for asset in assets.iter() {
+        let response = pool_instance.get_balances(_id.clone(), asset.clone()).call().await.unwrap();
+        assert_eq!(response.value, 0);
+    }
+
+    // Nothing left to settle
+    let result = _instance
+        .settle_batch()
+        .set_contracts(&[pool_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn cannot_fill_a_batch_with_dust() {
+    let (_instance, _id, wallets, pool_id, assets) = get_contract_instance().await;
+
+    // Orders below the minimum size are refused
+    let result = MyContract::new(_id.to_string(), wallets[1].clone())
+        .submit_order(assets[0].clone(), 99, 1)
+        .call_params(CallParameters::new(Some(99), Some(AssetId::from(*assets[0]))))
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // One owner can only take a few of the batch's slots
+    for _ in 0..4 {
+        submit_order(&_id, &wallets[1], &assets[0], 100, 1).await;
+    }
+    let result = MyContract::new(_id.to_string(), wallets[1].clone())
+        .submit_order(assets[0].clone(), 100, 1)
+        .call_params(CallParameters::new(Some(100), Some(AssetId::from(*assets[0]))))
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Others still get in
+    submit_order(&_id, &wallets[2], &assets[1], 100, 1).await;
+    let response = _instance.get_pending_orders().call().await.unwrap();
+    assert_eq!(response.value, 5);
+
+    // The limit is per batch, so the owner can order again once it settled
+    let response = _instance
+        .settle_batch()
+        .set_contracts(&[pool_id.clone()])
+        .append_variable_outputs(5)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 5);
+    let order_id = submit_order(&_id, &wallets[1], &assets[0], 100, 1).await;
+    assert_eq!(order_id, 5);
+}
+
+#[tokio::test]
+async fn can_refund_orders_the_clearing_cannot_fill() {
+    let (_instance, _id, wallets, pool_id, assets) = get_contract_instance().await;
+    let pool_instance = StableSwap::new(pool_id.to_string(), wallets[0].clone());
+
+    let a_before = get_wallet_balance(&wallets[1], &assets[0]).await;
+    let b_before = get_wallet_balance(&wallets[1], &assets[1]).await;
+
+    // More than 1:1 for A is more than the pool will give
+    submit_order(&_id, &wallets[1], &assets[0], 3000, 3001).await;
+    submit_order(&_id, &wallets[2], &assets[1], 1000, 990).await;
+    submit_order(&_id, &wallets[0], &assets[0], 500, 1).await;
+
+    let response = _instance
+        .settle_batch()
+        .set_contracts(&[pool_id.clone()])
+        .append_variable_outputs(3)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 2);
+
+    // The order sat out and got its input back
+    assert_eq!(get_wallet_balance(&wallets[1], &assets[0]).await, a_before);
+    assert_eq!(get_wallet_balance(&wallets[1], &assets[1]).await, b_before);
+
+    // Without it, B is the side with more input and its leftover goes through the pool
+    let stats = pool_instance.get_stats().call().await.unwrap().value;
+    assert_eq!(stats.swap_count, 1);
+    assert_eq!(stats.eth_volume, 0);
+    assert_eq!(stats.token_volume, 500);
+}
+
+#[tokio::test]
+async fn cannot_settle_away_from_the_ema() {
+    let (_instance, _id, wallets, pool_id, assets) = get_contract_instance().await;
+
+    submit_order(&_id, &wallets[1], &assets[0], 3000, 1).await;
+    submit_order(&_id, &wallets[2], &assets[1], 1000, 1).await;
+
+    // Someone moves the pool right before the batch settles
+    let pool_instance = StableSwap::new(pool_id.to_string(), wallets[1].clone());
+    pool_instance
+        .swap(90000, 1, 1000)
+        .call_params(CallParameters::new(Some(90000), Some(AssetId::from(*assets[0]))))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    // The EMA hasn't followed, so the batch isn't settled at the moved price
+    let result = _instance
+        .settle_batch()
+        .set_contracts(&[pool_id.clone()])
+        .append_variable_outputs(2)
+        .call()
+        .await;
+    assert!(result.is_err());
+    let response = _instance.get_pending_orders().call().await.unwrap();
+    assert_eq!(response.value, 2);
+}
+
+#[tokio::test]
+async fn can_refund_orders_of_a_stuck_batch() {
+    let (_instance, _id, wallets, pool_id, assets) = get_contract_instance().await;
+    let pool_instance = StableSwap::new(pool_id.to_string(), wallets[0].clone());
+
+    let a_before = get_wallet_balance(&wallets[1], &assets[0]).await;
+
+    submit_order(&_id, &wallets[1], &assets[0], 3000, 1).await;
+    submit_order(&_id, &wallets[2], &assets[1], 1000, 1).await;
+
+    // The pool is paused, so the residual swap and with it the whole settlement reverts
+    pool_instance.pause().call().await.unwrap();
+    let result = _instance
+        .settle_batch()
+        .set_contracts(&[pool_id.clone()])
+        .append_variable_outputs(2)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Orders stay in the batch until the settlement window has run out
+    let result = _instance.refund_order(0).append_variable_outputs(1).call().await;
+    assert!(result.is_err());
+
+    produce_blocks(&wallets[0], 22).await;
+
+    // Then anyone can send an order back to its owner, once
+    let keeper_instance = MyContract::new(_id.to_string(), wallets[2].clone());
+    let response = keeper_instance.refund_order(0).append_variable_outputs(1).call().await.unwrap();
+    assert_eq!(response.value, 3000);
+    assert_eq!(get_wallet_balance(&wallets[1], &assets[0]).await, a_before);
+    let result = keeper_instance.refund_order(0).append_variable_outputs(1).call().await;
+    assert!(result.is_err());
+
+    // A later settlement leaves the refunded order out
+    pool_instance.unpause().call().await.unwrap();
+    let response = _instance
+        .settle_batch()
+        .set_contracts(&[pool_id.clone()])
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 1);
+    assert_eq!(get_wallet_balance(&wallets[1], &assets[0]).await, a_before);
+    let response = _instance.get_pending_orders().call().await.unwrap();
+    assert_eq!(response.value, 0);
+
+    // Settled orders can't be refunded
+    let result = keeper_instance.refund_order(1).append_variable_outputs(1).call().await;
+    assert!(result.is_err());
+}
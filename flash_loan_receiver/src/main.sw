@@ -0,0 +1,75 @@
+contract;
+
+use ns_lib::{FlashLoanReceiver, FlashSwapReceiver};
+
+use std::{
+    chain::auth::*,
+    context::{*, call_frames::*},
+    contract_id::ContractId,
+    result::*,
+    revert::revert,
+    storage::*,
+    token::*,
+};
+
+storage {
+    repay: bool,
+    swap_payment: u64,
+}
+
+// Sample borrower used to exercise the pool's flash loans and flash swaps.
+// Pays the loan back with fee only when `repay` is set, and pays
+// `swap_payment` of the input asset for flash swaps.
+abi FlashLoanBorrower {
+    fn set_repay(repay: bool);
+    fn set_swap_payment(amount: u64);
+}
+
+impl FlashLoanBorrower for Contract {
+    fn set_repay(repay: bool) {
+        storage.repay = repay;
+    }
+
+    fn set_swap_payment(amount: u64) {
+        storage.swap_payment = amount;
+    }
+}
+
+impl FlashLoanReceiver for Contract {
+    fn on_flash_loan(asset_id: ContractId, amount: u64, fee: u64, data: b256) {
+        // An arbitrage or liquidation would use the funds here
+
+        if storage.repay {
+            let pool = get_msg_sender_contract_or_panic();
+            force_transfer(amount + fee, asset_id, pool);
+        };
+    }
+}
+
+impl FlashSwapReceiver for Contract {
+    fn on_flash_swap(asset_out: ContractId, amount_out: u64, asset_in: ContractId, data: b256) {
+        let payment = storage.swap_payment;
+        if payment > 0 {
+            let pool = get_msg_sender_contract_or_panic();
+            force_transfer(payment, asset_in, pool);
+        };
+    }
+}
+
+// Return the calling contract or panic
+fn get_msg_sender_contract_or_panic() -> ContractId {
+    let result: Result<Sender, AuthError> = msg_sender();
+    let mut ret = ~ContractId::from(0x0000000000000000000000000000000000000000000000000000000000000000);
+    if result.is_err() {
+        revert(0);
+    } else {
+        let unwrapped = result.unwrap();
+        if let Sender::ContractId(v) = unwrapped {
+            ret = v;
+        } else {
+            revert(0);
+        };
+    };
+
+    ret
+}
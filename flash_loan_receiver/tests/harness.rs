@@ -0,0 +1,41 @@
+use fuel_tx::ContractId;
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::test_helpers;
+
+// Load abi from json
+abigen!(MyContract, "out/debug/flash_loan_receiver-abi.json");
+
+async fn get_contract_instance() -> (MyContract, ContractId) {
+    // Launch a local network and deploy the contract
+    let wallet = launch_provider_and_get_wallet().await;
+
+    let id = Contract::deploy("./out/debug/flash_loan_receiver.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+
+    let instance = MyContract::new(id.to_string(), wallet);
+
+    (instance, id)
+}
+
+#[tokio::test]
+async fn can_get_contract_id() {
+    let (_instance, _id) = get_contract_instance().await;
+
+    // Now you have an instance of your contract you can use to test each function
+}
+#[tokio::test]
+async fn can_set_repay() {
+    let (_instance, _id) = get_contract_instance().await;
+
+    _instance.set_repay(true).call().await.unwrap();
+    _instance.set_repay(false).call().await.unwrap();
+}
+
+#[tokio::test]
+async fn can_set_swap_payment() {
+    let (_instance, _id) = get_contract_instance().await;
+
+    _instance.set_swap_payment(1100).call().await.unwrap();
+}
@@ -0,0 +1,303 @@
+contract;
+
+use std::{
+    assert::assert,
+    block::*,
+    chain::auth::*,
+    context::{*, call_frames::*},
+    contract_id::ContractId,
+    hash::*,
+    logging::log,
+    result::*,
+    revert::revert,
+    storage::*,
+    token::*,
+};
+
+storage {
+    owner: b256,
+    initialized: bool,
+    lp_asset: b256,
+    reward_token: b256,
+    reward_rate: u64,
+    total_staked: u64,
+    acc_reward_per_share: u64,
+    reward_remainder: u64,
+    last_reward_height: u64,
+}
+
+pub struct StakeLog {
+    user: b256,
+    amount: u64,
+}
+
+pub struct UnstakeLog {
+    user: b256,
+    amount: u64,
+}
+
+pub struct ClaimLog {
+    user: b256,
+    amount: u64,
+}
+
+pub struct RewardState {
+    total_staked: u64,
+    acc_reward_per_share: u64,
+    last_reward_height: u64,
+    reward_rate: u64,
+}
+
+// Storage delimited
+const S_STAKES: b256 = 0x0000000000000000000000000000000000000000000000000000000000000000;
+const S_REWARD_DEBTS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000001;
+const S_UNCLAIMED: b256 = 0x0000000000000000000000000000000000000000000000000000000000000002;
+
+// Fixed-point scale of the accumulated reward per staked LP token
+const REWARD_PRECISION = 1000000;
+
+// The part of token_contract the gauge mints rewards through
+abi Token {
+    fn mint_coins(mint_amount: u64);
+    fn force_transfer_coins(coins: u64, asset_id: ContractId, target: ContractId);
+}
+
+// Stakes the LP asset of a stable_swap pool and emits `reward_rate` reward tokens per block,
+// shared between stakers in proportion to their stake.
+abi Gauge {
+    fn initialize(owner: Sender, lp_asset: ContractId, reward_token: ContractId, reward_rate: u64);
+    fn get_owner() -> b256;
+    fn set_reward_rate(reward_rate: u64);
+    fn stake() -> u64;
+    fn unstake(amount: u64) -> u64;
+    fn claim() -> u64;
+    fn pending_rewards(user: Sender) -> u64;
+    fn get_stake(user: Sender) -> u64;
+    fn get_reward_state() -> RewardState;
+}
+
+impl Gauge for Contract {
+    fn initialize(owner: Sender, lp_asset: ContractId, reward_token: ContractId, reward_rate: u64) {
+        // Open to the first caller, the deployer initializes right after deploying and checks `get_owner`
+        assert(storage.initialized == false);
+        assert(lp_asset.into() != reward_token.into());
+
+        storage.owner = sender_key(owner);
+        storage.lp_asset = lp_asset.into();
+        storage.reward_token = reward_token.into();
+        storage.reward_rate = reward_rate;
+        storage.last_reward_height = height();
+        storage.initialized = true;
+    }
+
+    fn get_owner() -> b256 {
+        storage.owner
+    }
+
+    fn set_reward_rate(reward_rate: u64) {
+        only_owner();
+
+        // Blocks so far are paid at the old rate
+        update_rewards();
+        storage.reward_rate = reward_rate;
+    }
+
+    fn stake() -> u64 {
+        assert(storage.initialized);
+        assert(msg_asset_id().into() == storage.lp_asset);
+        let amount = msg_amount();
+        assert(amount > 0);
+
+        let user = get_msg_sender_key_or_panic();
+        update_rewards();
+        settle(user);
+
+        let new_stake = get::<u64>(key_stakes(user)) + amount;
+        store(key_stakes(user), new_stake);
+        store(key_reward_debts(user), reward_debt(new_stake));
+        storage.total_staked = storage.total_staked + amount;
+
+        log(StakeLog {
+            user: user, amount: amount
+        });
+
+        new_stake
+    }
+
+    fn unstake(amount: u64) -> u64 {
+        assert(amount > 0);
+
+        let sender = get_msg_sender_or_panic();
+        let user = sender_key(sender);
+        let current_stake = get::<u64>(key_stakes(user));
+        assert(current_stake >= amount);
+
+        update_rewards();
+        settle(user);
+
+        let new_stake = current_stake - amount;
+        store(key_stakes(user), new_stake);
+        store(key_reward_debts(user), reward_debt(new_stake));
+        storage.total_staked = storage.total_staked - amount;
+
+        transfer_to_sender(amount, ~ContractId::from(storage.lp_asset), sender);
+
+        log(UnstakeLog {
+            user: user, amount: amount
+        });
+
+        new_stake
+    }
+
+    fn claim() -> u64 {
+        let sender = get_msg_sender_or_panic();
+        let user = sender_key(sender);
+
+        update_rewards();
+        settle(user);
+
+        let amount = get::<u64>(key_unclaimed(user));
+        if amount > 0 {
+            store(key_unclaimed(user), 0);
+
+            // Mint the rewards on the token contract and pull them into the gauge before paying out
+            let reward_token = ~ContractId::from(storage.reward_token);
+            let token = abi(Token, storage.reward_token);
+            token.mint_coins(amount);
+            token.force_transfer_coins(amount, reward_token, contract_id());
+            transfer_to_sender(amount, reward_token, sender);
+        };
+
+        log(ClaimLog {
+            user: user, amount: amount
+        });
+
+        amount
+    }
+
+    fn pending_rewards(user: Sender) -> u64 {
+        let user_key = sender_key(user);
+        let stake = get::<u64>(key_stakes(user_key));
+
+        let earned = (stake * current_acc_reward_per_share()) / REWARD_PRECISION;
+        get::<u64>(key_unclaimed(user_key)) + earned - get::<u64>(key_reward_debts(user_key))
+    }
+
+    fn get_stake(user: Sender) -> u64 {
+        get::<u64>(key_stakes(sender_key(user)))
+    }
+
+    fn get_reward_state() -> RewardState {
+        RewardState {
+            total_staked: storage.total_staked,
+            acc_reward_per_share: storage.acc_reward_per_share,
+            last_reward_height: storage.last_reward_height,
+            reward_rate: storage.reward_rate,
+        }
+    }
+}
+
+// Accumulated reward per staked LP token, brought up to the current block
+fn current_acc_reward_per_share() -> u64 {
+    let mut acc = storage.acc_reward_per_share;
+    let total_staked = storage.total_staked;
+
+    // Nothing accrues while nobody is staked
+    if total_staked > 0 {
+        acc = acc + undistributed_rewards() / total_staked;
+    };
+
+    acc
+}
+
+// Rewards since the last update plus what earlier updates couldn't split, scaled by REWARD_PRECISION.
+// A large stake at a small rate can earn less than one unit per LP token in a block, that part
+// carries over instead of being rounded away.
+fn undistributed_rewards() -> u64 {
+    let mut rewards = storage.reward_remainder;
+    let current_height = height();
+    if current_height > storage.last_reward_height {
+        let elapsed = current_height - storage.last_reward_height;
+        rewards = rewards + elapsed * storage.reward_rate * REWARD_PRECISION;
+    };
+
+    rewards
+}
+
+// Write the accumulator for the blocks since the last update
+fn update_rewards() {
+    let total_staked = storage.total_staked;
+    if total_staked > 0 {
+        let rewards = undistributed_rewards();
+        storage.acc_reward_per_share = storage.acc_reward_per_share + rewards / total_staked;
+        storage.reward_remainder = rewards % total_staked;
+    };
+    storage.last_reward_height = height();
+}
+
+// Move a user's rewards since their last action into their unclaimed balance
+fn settle(user: b256) {
+    let stake = get::<u64>(key_stakes(user));
+    let earned = reward_debt(stake) - get::<u64>(key_reward_debts(user));
+    if earned > 0 {
+        store(key_unclaimed(user), get::<u64>(key_unclaimed(user)) + earned);
+    };
+    store(key_reward_debts(user), reward_debt(stake));
+}
+
+// Rewards a stake would have earned since the start at the current accumulator
+fn reward_debt(stake: u64) -> u64 {
+    (stake * storage.acc_reward_per_share) / REWARD_PRECISION
+}
+
+fn only_owner() {
+    assert(storage.initialized);
+    assert(get_msg_sender_key_or_panic() == storage.owner);
+}
+
+// Return the sender as a b256 key, whether it is an address or a contract
+fn get_msg_sender_key_or_panic() -> b256 {
+    sender_key(get_msg_sender_or_panic())
+}
+
+// Return the sender, whether it is an address or a contract, or panic
+fn get_msg_sender_or_panic() -> Sender {
+    let result: Result<Sender, AuthError> = msg_sender();
+    if result.is_err() {
+        revert(0);
+    };
+
+    result.unwrap()
+}
+
+// Send coins to an address as an output, or straight into a contract's balance
+fn transfer_to_sender(amount: u64, asset_id: ContractId, to: Sender) {
+    if let Sender::Address(v) = to {
+        transfer_to_output(amount, asset_id, v);
+    } else if let Sender::ContractId(v) = to {
+        force_transfer(amount, asset_id, v);
+    };
+}
+
+fn sender_key(sender: Sender) -> b256 {
+    let mut ret = 0x0000000000000000000000000000000000000000000000000000000000000000;
+    if let Sender::Address(v) = sender {
+        ret = v.into();
+    } else if let Sender::ContractId(v) = sender {
+        ret = v.into();
+    };
+
+    ret
+}
+
+fn key_stakes(user: b256) -> b256 {
+    sha256((S_STAKES, user))
+}
+
+fn key_reward_debts(user: b256) -> b256 {
+    sha256((S_REWARD_DEBTS, user))
+}
+
+fn key_unclaimed(user: b256) -> b256 {
+    sha256((S_UNCLAIMED, user))
+}
@@ -0,0 +1,240 @@
+use fuel_tx::{AssetId, ContractId};
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::test_helpers;
+use test_utils::{deploy_with_salt, get_funded_token, get_pool_with_liquidity, get_wallet_balance, launch_provider_and_get_wallets, produce_blocks, provide_liquidity};
+
+// Load abi from json
+abigen!(MyContract, "out/debug/gauge-abi.json");
+
+const REWARD_RATE: u64 = 1000;
+
+// Two wallets holding LP tokens of the same pool, and a gauge paying a fresh reward token for them
+async fn get_contract_instance() -> (MyContract, ContractId, [LocalWallet; 2], ContractId, ContractId) {
+    // Launch a local network with two wallets and deploy the contract
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+
+    let token_a_id = get_funded_token(&wallets, 1, 100000).await;
+    let token_b_id = get_funded_token(&wallets, 2, 100000).await;
+    let assets = [token_a_id, token_b_id];
+
+    // Both wallets become LPs
+    let pool_id = get_pool_with_liquidity(&wallet, 1, assets.clone(), 10000).await;
+    let lp_minted = provide_liquidity(&wallets[1], &pool_id, &assets, 10000).await;
+    assert!(lp_minted >= 3000);
+
+    let reward_token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallet, 3).await;
+
+    let id = Contract::deploy("./out/debug/gauge.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let instance = MyContract::new(id.to_string(), wallet.clone());
+
+    // The pool's LP asset is its contract id
+    instance
+        .initialize(Sender::Address(wallet.address()), pool_id.clone(), reward_token_id.clone(), REWARD_RATE)
+        .call()
+        .await
+        .unwrap();
+    let response = instance.get_owner().call().await.unwrap();
+    assert_eq!(response.value, *wallet.address());
+
+    (instance, id, [wallets[0].clone(), wallets[1].clone()], pool_id, reward_token_id)
+}
+
+#[tokio::test]
+async fn can_split_rewards_between_stakers() {
+    let (_instance, _id, wallets, pool_id, reward_token_id) = get_contract_instance().await;
+    let instance_a = MyContract::new(_id.to_string(), wallets[0].clone());
+    let instance_b = MyContract::new(_id.to_string(), wallets[1].clone());
+    let lp_asset_id = AssetId::from(*pool_id);
+
+    // A stakes alone first
+    let response = instance_a
+        .stake()
+        .call_params(CallParameters::new(Some(1000), Some(lp_asset_id)))
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 1000);
+    let height_a = _instance.get_reward_state().call().await.unwrap().value.last_reward_height;
+
+    // B joins a few blocks later with three times the stake
+    let response = instance_b
+        .stake()
+        .call_params(CallParameters::new(Some(3000), Some(lp_asset_id)))
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 3000);
+    let state = _instance.get_reward_state().call().await.unwrap().value;
+    let height_b = state.last_reward_height;
+    assert!(height_b > height_a);
+    assert_eq!(state.total_staked, 4000);
+
+    // Everything before B joined went to A
+    let response = _instance
+        .pending_rewards(Sender::Address(wallets[0].address()))
+        .call()
+        .await
+        .unwrap();
+    assert!(response.value >= REWARD_RATE * (height_b - height_a));
+
+    // A claims: all of the rate until B joined, a quarter of it afterwards
+    let claimed_a = instance_a
+        .claim()
+        .set_contracts(&[reward_token_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    let height_c = _instance.get_reward_state().call().await.unwrap().value.last_reward_height;
+    assert_eq!(
+        claimed_a,
+        REWARD_RATE * (height_b - height_a) + REWARD_RATE * (height_c - height_b) / 4
+    );
+    assert_eq!(get_wallet_balance(&wallets[0], &reward_token_id).await, claimed_a);
+
+    // B claims three quarters of the rate since joining
+    let claimed_b = instance_b
+        .claim()
+        .set_contracts(&[reward_token_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    let height_d = _instance.get_reward_state().call().await.unwrap().value.last_reward_height;
+    assert_eq!(claimed_b, REWARD_RATE * 3 * (height_d - height_b) / 4);
+    assert_eq!(get_wallet_balance(&wallets[1], &reward_token_id).await, claimed_b);
+
+    // Claiming again only pays out the blocks since the last claim
+    let response = instance_b
+        .claim()
+        .set_contracts(&[reward_token_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    let height_e = _instance.get_reward_state().call().await.unwrap().value.last_reward_height;
+    assert_eq!(response.value, REWARD_RATE * 3 * (height_e - height_d) / 4);
+}
+
+#[tokio::test]
+async fn can_unstake() {
+    let (_instance, _id, wallets, pool_id, _reward_token_id) = get_contract_instance().await;
+    let lp_asset_id = AssetId::from(*pool_id);
+
+    _instance
+        .stake()
+        .call_params(CallParameters::new(Some(1000), Some(lp_asset_id)))
+        .call()
+        .await
+        .unwrap();
+    let lp_before = get_wallet_balance(&wallets[0], &pool_id).await;
+
+    let response = _instance
+        .unstake(400)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 600);
+    assert_eq!(get_wallet_balance(&wallets[0], &pool_id).await, lp_before + 400);
+
+    let response = _instance
+        .get_stake(Sender::Address(wallets[0].address()))
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 600);
+
+    // Rewards keep accruing on what is left
+    let response = _instance
+        .pending_rewards(Sender::Address(wallets[0].address()))
+        .call()
+        .await
+        .unwrap();
+    assert!(response.value > 0);
+
+    // Can't take out more than is staked
+    let result = _instance.unstake(601).append_variable_outputs(1).call().await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_accrue_small_rate_on_large_stake() {
+    let wallets = launch_provider_and_get_wallets(1).await;
+    let wallet = wallets[0].clone();
+
+    // About three million LP tokens, so one reward a block is less than one unit of REWARD_PRECISION per LP token
+    let assets = [
+        get_funded_token(&wallets, 1, 1_500_000).await,
+        get_funded_token(&wallets, 2, 1_500_000).await,
+    ];
+    let pool_id = get_pool_with_liquidity(&wallet, 1, assets, 1_500_000).await;
+    let lp_amount = get_wallet_balance(&wallet, &pool_id).await;
+    assert!(lp_amount > 2_000_000);
+
+    let reward_token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallet, 3).await;
+    let id = Contract::deploy("./out/debug/gauge.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let instance = MyContract::new(id.to_string(), wallet.clone());
+    instance
+        .initialize(Sender::Address(wallet.address()), pool_id.clone(), reward_token_id.clone(), 1)
+        .call()
+        .await
+        .unwrap();
+
+    instance
+        .stake()
+        .call_params(CallParameters::new(Some(lp_amount), Some(AssetId::from(*pool_id))))
+        .call()
+        .await
+        .unwrap();
+    let height_a = instance.get_reward_state().call().await.unwrap().value.last_reward_height;
+
+    // Every update splits less than one unit per LP token, the remainder carries over
+    for _ in 0..10 {
+        produce_blocks(&wallet, 1).await;
+        instance.set_reward_rate(1).call().await.unwrap();
+    }
+
+    // The sole staker gets every block's reward, short of the rounding of its own share
+    let claimed = instance
+        .claim()
+        .set_contracts(&[reward_token_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    let height_b = instance.get_reward_state().call().await.unwrap().value.last_reward_height;
+    let elapsed = height_b - height_a;
+    assert!(claimed <= elapsed);
+    assert!(claimed + lp_amount / 1_000_000 + 1 >= elapsed);
+    assert_eq!(get_wallet_balance(&wallet, &reward_token_id).await, claimed);
+}
+
+#[tokio::test]
+async fn cannot_stake_other_assets_or_set_rate_as_non_owner() {
+    let (_instance, _id, wallets, _pool_id, _reward_token_id) = get_contract_instance().await;
+
+    let result = _instance
+        .stake()
+        .call_params(CallParameters::new(Some(1000), None))
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    let instance_b = MyContract::new(_id.to_string(), wallets[1].clone());
+    let result = instance_b.set_reward_rate(REWARD_RATE * 2).call().await;
+    assert!(result.is_err());
+
+    _instance.set_reward_rate(REWARD_RATE * 2).call().await.unwrap();
+    let response = _instance.get_reward_state().call().await.unwrap();
+    assert_eq!(response.value.reward_rate, REWARD_RATE * 2);
+}
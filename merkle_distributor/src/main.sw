@@ -0,0 +1,117 @@
+contract;
+
+use std::{
+    address::Address,
+    assert::assert,
+    context::{*, call_frames::*},
+    contract_id::ContractId,
+    hash::*,
+    logging::log,
+    math::*,
+    storage::*,
+    token::*,
+};
+
+storage {
+    initialized: bool,
+    token: b256,
+    merkle_root: b256,
+    depth: u64,
+}
+
+pub struct ClaimLog {
+    index: u64,
+    account: b256,
+    amount: u64,
+}
+
+// Storage delimited
+const S_CLAIMED: b256 = 0x0000000000000000000000000000000000000000000000000000000000000000;
+
+// Deepest tree a proof can cover, 2^16 accounts
+const MAX_PROOF_DEPTH = 16;
+
+// Pays out a snapshot of (account, amount) pairs committed to as a Merkle root.
+// Leaves are sha256((index, account, amount)), padded with zero leaves to a power of two,
+// and the index picks the side of each sibling on the way up. See tree/lib.rs for the generator.
+abi MerkleDistributor {
+    fn initialize(token: ContractId, merkle_root: b256, depth: u64);
+    fn fund();
+    fn claim(index: u64, account: Address, amount: u64, proof: [b256; 16]);
+    fn is_claimed(index: u64) -> bool;
+    fn get_merkle_root() -> b256;
+    fn get_token() -> ContractId;
+}
+
+impl MerkleDistributor for Contract {
+    fn initialize(token: ContractId, merkle_root: b256, depth: u64) {
+        // Open to the first caller, so check `get_merkle_root` and `get_token` before funding
+        assert(storage.initialized == false);
+        assert(depth <= MAX_PROOF_DEPTH);
+
+        storage.token = token.into();
+        storage.merkle_root = merkle_root;
+        storage.depth = depth;
+        storage.initialized = true;
+    }
+
+    fn fund() {
+        assert(storage.initialized);
+        assert(msg_asset_id().into() == storage.token);
+        assert(msg_amount() > 0);
+    }
+
+    fn claim(index: u64, account: Address, amount: u64, proof: [b256; 16]) {
+        assert(storage.initialized);
+        assert(get::<bool>(key_claimed(index)) == false);
+
+        let leaf = sha256((index, account.into(), amount));
+        assert(verify_proof(leaf, index, proof));
+
+        // Mark before paying out, anyone may submit the claim but only `account` is paid
+        store(key_claimed(index), true);
+        transfer_to_output(amount, ~ContractId::from(storage.token), account);
+
+        log(ClaimLog {
+            index: index, account: account.into(), amount: amount
+        });
+    }
+
+    fn is_claimed(index: u64) -> bool {
+        get::<bool>(key_claimed(index))
+    }
+
+    fn get_merkle_root() -> b256 {
+        storage.merkle_root
+    }
+
+    fn get_token() -> ContractId {
+        ~ContractId::from(storage.token)
+    }
+}
+
+// Hash up from the leaf, the bits of the index say whether the sibling sits left or right
+fn verify_proof(leaf: b256, index: u64, proof: [b256; 16]) -> bool {
+    let depth = storage.depth;
+    // Indices beyond the padded tree would wrap around onto other leaves
+    assert(index < exp(2, depth));
+
+    let mut node = leaf;
+    let mut position = index;
+    let mut i = 0;
+    while i < depth {
+        if position % 2 == 0 {
+            node = sha256((node, proof[i]));
+        } else {
+            node = sha256((proof[i], node));
+        };
+        position = position / 2;
+        i = i + 1;
+    }
+
+    node == storage.merkle_root
+}
+
+fn key_claimed(index: u64) -> b256 {
+    sha256((S_CLAIMED, index))
+}
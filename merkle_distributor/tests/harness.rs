@@ -0,0 +1,168 @@
+use fuel_tx::{AssetId, ContractId};
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::test_helpers;
+use merkle_distributor::{leaf_hash, verify, MerkleTree};
+use test_utils::get_wallet_balance;
+
+// Load abi from json
+abigen!(MyContract, "out/debug/merkle_distributor-abi.json");
+abigen!(TestToken, "../token_contract/out/debug/token_contract-abi.json");
+
+// Snapshot of three wallets as the generator reads it
+fn snapshot_csv(wallets: &[LocalWallet]) -> String {
+    let mut csv = String::from("account,amount\n");
+    for (i, wallet) in wallets.iter().enumerate() {
+        csv.push_str(&format!("0x{},{}\n", hex::encode(*wallet.address()), (i as u64 + 1) * 100));
+    }
+
+    csv
+}
+
+// A distributor funded with enough reward tokens for the snapshot of three wallets
+async fn get_contract_instance() -> (MyContract, ContractId, Vec<LocalWallet>, ContractId, MerkleTree) {
+    // Launch a local network and deploy the contract
+    let wallets = launch_custom_provider_and_get_wallets(
+        WalletsConfig::new(Some(3), Some(1), Some(1_000_000)),
+        None,
+    )
+    .await;
+    let wallet = wallets[0].clone();
+
+    let token_id = Contract::deploy("../token_contract/out/debug/token_contract.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let token_instance = TestToken::new(token_id.to_string(), wallet.clone());
+    token_instance.mint_coins(1000).call().await.unwrap();
+    token_instance
+        .transfer_coins_to_output(1000, token_id.clone(), wallet.address())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    let id = Contract::deploy("./out/debug/merkle_distributor.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let instance = MyContract::new(id.to_string(), wallet.clone());
+
+    let tree = MerkleTree::from_csv(&snapshot_csv(&wallets)).unwrap();
+    instance
+        .initialize(token_id.clone(), tree.root(), tree.depth())
+        .call()
+        .await
+        .unwrap();
+    instance
+        .fund()
+        .call_params(CallParameters::new(Some(600), Some(AssetId::from(*token_id))))
+        .call()
+        .await
+        .unwrap();
+
+    (instance, id, wallets, token_id, tree)
+}
+
+#[tokio::test]
+async fn can_build_tree_from_csv() {
+    let wallets = launch_custom_provider_and_get_wallets(
+        WalletsConfig::new(Some(3), Some(1), Some(1_000_000)),
+        None,
+    )
+    .await;
+
+    let tree = MerkleTree::from_csv(&snapshot_csv(&wallets)).unwrap();
+    assert_eq!(tree.entries().len(), 3);
+    // Three leaves are padded to four
+    assert_eq!(tree.depth(), 2);
+
+    for (index, entry) in tree.entries().iter().enumerate() {
+        let leaf = leaf_hash(index as u64, entry.account, entry.amount);
+        assert!(verify(tree.root(), leaf, index as u64, &tree.proof(index)));
+    }
+
+    let json = tree.to_json();
+    assert_eq!(json["depth"], 2);
+    assert_eq!(json["claims"][1]["amount"], 200);
+    assert_eq!(json["claims"][1]["proof"].as_array().unwrap().len(), 2);
+
+    assert!(MerkleTree::from_csv("account,amount\n").is_err());
+    assert!(MerkleTree::from_csv("0x01,100\n").is_err());
+}
+
+#[tokio::test]
+async fn can_claim() {
+    let (_instance, _id, wallets, token_id, tree) = get_contract_instance().await;
+
+    // Anyone may submit a claim, the coins go to the account in the leaf
+    let response = _instance.is_claimed(1).call().await.unwrap();
+    assert_eq!(response.value, false);
+    _instance
+        .claim(1, wallets[1].address(), 200, tree.proof_array(1))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(get_wallet_balance(&wallets[1], &token_id).await, 200);
+    let response = _instance.is_claimed(1).call().await.unwrap();
+    assert_eq!(response.value, true);
+
+    // The last leaf sits next to a padding leaf
+    let instance_c = MyContract::new(_id.to_string(), wallets[2].clone());
+    instance_c
+        .claim(2, wallets[2].address(), 300, tree.proof_array(2))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(get_wallet_balance(&wallets[2], &token_id).await, 300);
+}
+
+#[tokio::test]
+async fn cannot_claim_twice_or_with_a_bad_proof() {
+    let (_instance, _id, wallets, _token_id, tree) = get_contract_instance().await;
+
+    // Amount not in the snapshot
+    let result = _instance
+        .claim(1, wallets[1].address(), 250, tree.proof_array(1))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Another index's proof
+    let result = _instance
+        .claim(1, wallets[1].address(), 200, tree.proof_array(0))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Someone else's leaf
+    let result = _instance
+        .claim(1, wallets[2].address(), 200, tree.proof_array(1))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Padding leaves can't be claimed
+    let result = _instance
+        .claim(3, wallets[0].address(), 0, tree.proof_array(3))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    _instance
+        .claim(1, wallets[1].address(), 200, tree.proof_array(1))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    let result = _instance
+        .claim(1, wallets[1].address(), 200, tree.proof_array(1))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+}
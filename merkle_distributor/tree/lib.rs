@@ -0,0 +1,275 @@
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+// Deepest tree the contract accepts proofs for, mirrors MAX_PROOF_DEPTH in src/main.sw
+pub const MAX_PROOF_DEPTH: usize = 16;
+
+// One row of the snapshot
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub account: [u8; 32],
+    pub amount: u64,
+}
+
+// Merkle tree over a snapshot, leaves are padded with zeros up to a power of two.
+// `layers[0]` holds the leaves and the last layer holds the root alone.
+pub struct MerkleTree {
+    entries: Vec<Entry>,
+    layers: Vec<Vec<[u8; 32]>>,
+}
+
+impl MerkleTree {
+    pub fn new(entries: Vec<Entry>) -> Result<Self, String> {
+        if entries.is_empty() {
+            return Err("snapshot has no entries".to_string());
+        }
+        let size = entries.len().next_power_of_two();
+        if size > 1 << MAX_PROOF_DEPTH {
+            return Err(format!("snapshot has more than {} entries", 1 << MAX_PROOF_DEPTH));
+        }
+
+        let mut leaves: Vec<[u8; 32]> = entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| leaf_hash(index as u64, entry.account, entry.amount))
+            .collect();
+        leaves.resize(size, [0u8; 32]);
+
+        let mut layers = vec![leaves];
+        while layers.last().unwrap().len() > 1 {
+            let next = layers
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| hash_pair(pair[0], pair[1]))
+                .collect();
+            layers.push(next);
+        }
+
+        Ok(MerkleTree { entries, layers })
+    }
+
+    // Parse `account,amount` lines, the account as 32 bytes of hex. A header line is skipped.
+    pub fn from_csv(csv: &str) -> Result<Self, String> {
+        let mut entries = Vec::new();
+        for (line_number, line) in csv.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || (line_number == 0 && line.starts_with("account")) {
+                continue;
+            }
+
+            let mut fields = line.split(',').map(|field| field.trim());
+            let (account, amount) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(account), Some(amount), None) => (account, amount),
+                _ => return Err(format!("line {}: expected `account,amount`", line_number + 1)),
+            };
+
+            entries.push(Entry {
+                account: parse_account(account).map_err(|e| format!("line {}: {}", line_number + 1, e))?,
+                amount: amount
+                    .parse()
+                    .map_err(|_| format!("line {}: invalid amount `{}`", line_number + 1, amount))?,
+            });
+        }
+
+        MerkleTree::new(entries)
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.layers.last().unwrap()[0]
+    }
+
+    // Number of proof elements per claim, the `depth` the contract is initialized with
+    pub fn depth(&self) -> u64 {
+        (self.layers.len() - 1) as u64
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    // Siblings from the leaf up to the root
+    pub fn proof(&self, index: usize) -> Vec<[u8; 32]> {
+        let mut position = index;
+        let mut proof = Vec::new();
+        for layer in &self.layers[..self.layers.len() - 1] {
+            proof.push(layer[position ^ 1]);
+            position /= 2;
+        }
+
+        proof
+    }
+
+    // The proof as the fixed-size argument `claim` takes, unused slots are zero
+    pub fn proof_array(&self, index: usize) -> [[u8; 32]; MAX_PROOF_DEPTH] {
+        let mut ret = [[0u8; 32]; MAX_PROOF_DEPTH];
+        for (i, node) in self.proof(index).into_iter().enumerate() {
+            ret[i] = node;
+        }
+
+        ret
+    }
+
+    // Root, depth and a claim with its proof for every account
+    pub fn to_json(&self) -> Value {
+        let claims: Vec<Value> = self
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                json!({
+                    "index": index,
+                    "account": to_hex(&entry.account),
+                    "amount": entry.amount,
+                    "proof": self.proof(index).iter().map(to_hex).collect::<Vec<String>>(),
+                })
+            })
+            .collect();
+
+        json!({
+            "root": to_hex(&self.root()),
+            "depth": self.depth(),
+            "claims": claims,
+        })
+    }
+}
+
+// Hash a claim the way Sway hashes a (u64, b256, u64) tuple: big-endian words back to back
+pub fn leaf_hash(index: u64, account: [u8; 32], amount: u64) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(index.to_be_bytes());
+    hasher.update(account);
+    hasher.update(amount.to_be_bytes());
+    hasher.finalize().into()
+}
+
+// Hash two b256 words the way Sway hashes a (b256, b256) tuple
+pub fn hash_pair(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(a);
+    hasher.update(b);
+    hasher.finalize().into()
+}
+
+// Same walk as `verify_proof` in the contract
+pub fn verify(root: [u8; 32], leaf: [u8; 32], index: u64, proof: &[[u8; 32]]) -> bool {
+    let mut node = leaf;
+    let mut position = index;
+    for sibling in proof {
+        node = if position & 1 == 0 {
+            hash_pair(node, *sibling)
+        } else {
+            hash_pair(*sibling, node)
+        };
+        position /= 2;
+    }
+
+    node == root
+}
+
+fn parse_account(account: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(account.trim_start_matches("0x")).map_err(|_| format!("invalid account `{}`", account))?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("account `{}` is not 32 bytes", account))
+}
+
+fn to_hex(bytes: &[u8; 32]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(count: usize) -> Vec<Entry> {
+        (0..count)
+            .map(|i| Entry {
+                account: [(i % 255) as u8 + 1; 32],
+                amount: 1000 * (i as u64 + 1),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn leaf_hash_matches_sway_tuple_encoding() {
+        // sha256 of the 72 bytes 0x0000000000000003 ++ 0xab * 32 ++ 0x00000000000003e8
+        let expected: [u8; 32] = hex::decode("4110dd68989280f483f6f76a41134f82e4012d693e86089e9c92451ee5251d70")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(leaf_hash(3, [0xab; 32], 1000), expected);
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&3u64.to_be_bytes());
+        bytes.extend_from_slice(&[0xab; 32]);
+        bytes.extend_from_slice(&1000u64.to_be_bytes());
+        let digest: [u8; 32] = Sha256::digest(&bytes).into();
+        assert_eq!(leaf_hash(3, [0xab; 32], 1000), digest);
+
+        // The index is part of the leaf, the same claim at another index hashes differently
+        assert_ne!(leaf_hash(3, [0xab; 32], 1000), leaf_hash(4, [0xab; 32], 1000));
+    }
+
+    #[test]
+    fn pads_with_zero_leaves() {
+        let tree = MerkleTree::new(entries(3)).unwrap();
+        assert_eq!(tree.depth(), 2);
+        assert_eq!(tree.layers[0].len(), 4);
+        assert_eq!(tree.layers[0][3], [0u8; 32]);
+        assert_eq!(tree.proof(2)[0], [0u8; 32]);
+
+        // The fourth leaf is the zero word itself, so the second pair hashes the third leaf with zeros
+        let right = hash_pair(leaf_hash(2, [3; 32], 3000), [0u8; 32]);
+        let left = hash_pair(leaf_hash(0, [1; 32], 1000), leaf_hash(1, [2; 32], 2000));
+        assert_eq!(tree.root(), hash_pair(left, right));
+
+        // A single entry is its own root, with nothing to prove
+        let tree = MerkleTree::new(entries(1)).unwrap();
+        assert_eq!(tree.depth(), 0);
+        assert_eq!(tree.root(), leaf_hash(0, [1; 32], 1000));
+        assert!(tree.proof(0).is_empty());
+    }
+
+    #[test]
+    fn rejects_snapshots_past_max_depth() {
+        assert!(MerkleTree::new(vec![]).is_err());
+        assert!(MerkleTree::new(entries((1 << MAX_PROOF_DEPTH) + 1)).is_err());
+
+        let tree = MerkleTree::new(entries(1 << MAX_PROOF_DEPTH)).unwrap();
+        assert_eq!(tree.depth(), MAX_PROOF_DEPTH as u64);
+    }
+
+    #[test]
+    fn proofs_verify_against_on_chain_leaves() {
+        let tree = MerkleTree::new(entries(5)).unwrap();
+        assert_eq!(tree.depth(), 3);
+
+        for (index, entry) in tree.entries().iter().enumerate() {
+            let leaf = leaf_hash(index as u64, entry.account, entry.amount);
+            let proof = tree.proof(index);
+            assert!(verify(tree.root(), leaf, index as u64, &proof));
+
+            // Unused slots of the contract's argument are zero
+            let proof_array = tree.proof_array(index);
+            assert_eq!(&proof_array[..proof.len()], &proof[..]);
+            assert!(proof_array[proof.len()..].iter().all(|node| *node == [0u8; 32]));
+
+            // Another amount, or the proof of another index, doesn't verify
+            let forged = leaf_hash(index as u64, entry.account, entry.amount + 1);
+            assert!(!verify(tree.root(), forged, index as u64, &proof));
+            assert!(!verify(tree.root(), leaf, (index as u64) ^ 1, &proof));
+        }
+    }
+
+    #[test]
+    fn parses_csv_snapshots() {
+        let csv = format!("account,amount\n0x{},1000\n{},2000\n", hex::encode([1u8; 32]), hex::encode([2u8; 32]));
+        let tree = MerkleTree::from_csv(&csv).unwrap();
+        assert_eq!(tree.entries(), &entries(2)[..]);
+
+        assert!(MerkleTree::from_csv("0x01,1000").is_err());
+        assert!(MerkleTree::from_csv(&format!("{},ten", hex::encode([1u8; 32]))).is_err());
+        assert!(MerkleTree::from_csv(&format!("{},1,2", hex::encode([1u8; 32]))).is_err());
+    }
+}
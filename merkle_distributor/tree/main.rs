@@ -0,0 +1,25 @@
+use merkle_distributor::MerkleTree;
+use std::{env, fs, process};
+
+// Usage: merkle_tree <snapshot.csv>
+// Prints the root, depth and every claim with its proof as JSON
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: merkle_tree <snapshot.csv>");
+            process::exit(1);
+        }
+    };
+
+    let csv = fs::read_to_string(&path).unwrap_or_else(|e| {
+        eprintln!("could not read {}: {}", path, e);
+        process::exit(1);
+    });
+    let tree = MerkleTree::from_csv(&csv).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    println!("{}", serde_json::to_string_pretty(&tree.to_json()).unwrap());
+}
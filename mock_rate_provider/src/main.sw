@@ -0,0 +1,26 @@
+contract;
+
+use ns_lib::RateProvider;
+
+use std::storage::*;
+
+storage {
+    rate: u64,
+}
+
+// Stand-in for the rate oracle of a yield-bearing asset, the rate is whatever was set last
+abi MockRateProvider {
+    fn set_rate(rate: u64);
+}
+
+impl MockRateProvider for Contract {
+    fn set_rate(rate: u64) {
+        storage.rate = rate;
+    }
+}
+
+impl RateProvider for Contract {
+    fn get_rate() -> u64 {
+        storage.rate
+    }
+}
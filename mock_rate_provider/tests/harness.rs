@@ -0,0 +1,29 @@
+use fuel_tx::ContractId;
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::test_helpers;
+
+// Load abi from json
+abigen!(MyContract, "out/debug/mock_rate_provider-abi.json");
+
+async fn get_contract_instance() -> (MyContract, ContractId) {
+    // Launch a local network and deploy the contract
+    let wallet = launch_provider_and_get_wallet().await;
+
+    let id = Contract::deploy("./out/debug/mock_rate_provider.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+
+    let instance = MyContract::new(id.to_string(), wallet);
+
+    (instance, id)
+}
+
+#[tokio::test]
+async fn can_set_rate() {
+    let (_instance, _id) = get_contract_instance().await;
+
+    _instance.set_rate(1_050_000).call().await.unwrap();
+    let response = _instance.get_rate().call().await.unwrap();
+    assert_eq!(response.value, 1_050_000);
+}
@@ -1,5 +1,48 @@
 library ns_lib;
 
+use std::{assert::assert, block::{height, timestamp}, chain::auth::Sender, contract_id::ContractId};
+
+pub struct RemoveLiquidityReturn {
+    eth_amount: u64,
+    token_amount: u64,
+}
+
+pub struct CumulativePrices {
+    eth_price_cumulative: u64,
+    token_price_cumulative: u64,
+    height: u64,
+}
+
+pub struct TwapPrices {
+    eth_price: u64,
+    token_price: u64,
+}
+
+// Running totals of a pool's trading, volumes are of the asset going in and fees of the asset they
+// were taken in
+pub struct PoolStats {
+    eth_volume: u64,
+    token_volume: u64,
+    eth_fees: u64,
+    token_fees: u64,
+    swap_count: u64,
+    last_trade_height: u64,
+}
+
+// Limits on how far a single trade may move a pool, both over 10^6 and 0 when off
+pub struct CircuitBreakerLimits {
+    max_trade_ratio: u64,
+    max_price_deviation: u64,
+}
+
+// Input of a two-phase swap waiting for its reveal, `height` is the block it was committed in
+pub struct SwapCommitment {
+    owner: b256,
+    asset_id: ContractId,
+    amount: u64,
+    height: u64,
+}
+
 pub fn abs(x: u64, y: u64) -> u64 {
     let mut res: u64 = 0;
     if x >= y {
@@ -8,4 +51,100 @@ pub fn abs(x: u64, y: u64) -> u64 {
         res = y - x;
     }
     res
-}
\ No newline at end of file
+}
+
+// Fuel block timestamps are TAI64, which start at 2^62, far above any block height
+const TAI64_OFFSET = 4611686018427387904;
+
+// Revert once a deadline has passed. A deadline at or above the TAI64 offset is a block timestamp,
+// anything below it is a block height.
+pub fn check_deadline(deadline: u64) {
+    if deadline >= TAI64_OFFSET {
+        assert(deadline > timestamp());
+    } else {
+        assert(deadline > height());
+    };
+}
+
+// Callback invoked by the pool on the borrowing contract during `flash_loan`.
+// The receiver must send `amount + fee` of `asset_id` back to the pool before returning.
+abi FlashLoanReceiver {
+    fn on_flash_loan(asset_id: ContractId, amount: u64, fee: u64, data: b256);
+}
+
+// Callback invoked by the pool on the receiving contract during `flash_swap`.
+// The receiver gets `amount_out` of `asset_out` up front and must send enough
+// `asset_in` back to the pool to keep the StableSwap invariant after fees.
+abi FlashSwapReceiver {
+    fn on_flash_swap(asset_out: ContractId, amount_out: u64, asset_in: ContractId, data: b256);
+}
+
+// Oracle for the rate of a yield-bearing or wrapped asset against its peg, with 6 decimals.
+// A pool multiplies the asset's balance by it before the balance enters the invariant.
+abi RateProvider {
+    fn get_rate() -> u64;
+}
+
+// Interface of the stable_swap pool, shared with the contracts that call into it
+abi NuclearSwap {
+    fn get_balance(token: ContractId) -> u64;
+    fn get_balances(target: ContractId, asset_id: ContractId) -> u64;
+    fn deposit();
+    fn get_deposit(owner: Sender, asset_id: ContractId) -> u64;
+    fn withdraw(amount: u64, asset_id: ContractId);
+    fn withdraw_all();
+    fn transfer_deposit(to: Sender, asset_id: ContractId, amount: u64);
+    fn transfer_deposit_from(from: Sender, to: Sender, asset_id: ContractId, amount: u64);
+    fn approve_operator(operator: Sender, asset_id: ContractId, amount: u64);
+    fn get_allowance(owner: Sender, operator: Sender, asset_id: ContractId) -> u64;
+    //fn getVirtualPrice() -> u64;
+    fn swap(dx: u64, minDy: u64, deadline: u64) -> u64;
+    fn swap_with_referrer(dx: u64, min_dy: u64, referrer: Sender, referral_fee_bps: u64, deadline: u64) -> u64;
+    fn commit_swap(hash: b256);
+    fn reveal_swap(asset_in: ContractId, dx: u64, min_dy: u64, salt: b256) -> u64;
+    fn refund_swap(hash: b256) -> u64;
+    fn get_commitment(owner: Sender, hash: b256) -> SwapCommitment;
+    fn add_liquidity(min_liquidity: u64, deadline: u64) -> u64;
+    fn remove_liquidity(min_eth: u64, min_tokens: u64, deadline: u64) -> RemoveLiquidityReturn;
+    fn flash_loan(asset_id: ContractId, amount: u64, receiver: ContractId, data: b256);
+    fn flash_swap(asset_out: ContractId, amount_out: u64, receiver: ContractId, data: b256) -> u64;
+    fn test_current_reserve(token_id: b256) -> u64;
+    fn get_cumulative_prices() -> CumulativePrices;
+    fn get_twap(window: u64) -> TwapPrices;
+    fn get_stats() -> PoolStats;
+    fn set_circuit_breaker(max_trade_ratio: u64, max_price_deviation: u64);
+    fn get_circuit_breaker() -> CircuitBreakerLimits;
+    fn initialize(owner: Sender, assets: [ContractId; 2], amplification: u64, fee: u64, minimum_liquidity: u64);
+    fn get_owner() -> b256;
+    fn set_ma_exp_time(ma_exp_time: u64);
+    fn set_offpeg_fee_multiplier(offpeg_fee_multiplier: u64);
+    fn get_offpeg_fee_multiplier() -> u64;
+    fn dynamic_fee(i: u64, j: u64) -> u64;
+    fn set_rate_provider(asset_id: ContractId, rate_provider: ContractId);
+    fn get_rate_provider(asset_id: ContractId) -> ContractId;
+    fn get_rates() -> [u64; 2];
+    fn get_p() -> u64;
+    fn price_oracle() -> u64;
+    fn spot_price(i: u64, j: u64) -> u64;
+    fn price_impact(i: u64, j: u64, dx: u64) -> u64;
+    fn get_assets() -> [ContractId; 2];
+    fn get_dy(asset_in: ContractId, dx: u64) -> u64;
+    fn get_dx(asset_out: ContractId, dy: u64) -> u64;
+    fn get_imbalance_fees(amounts: [u64; 2]) -> [u64; 2];
+    fn get_virtual_price() -> u64;
+    fn set_base_pool(base_pool: ContractId);
+    fn exchange_underlying(asset_out: ContractId, min_dy: u64, deadline: u64) -> u64;
+    fn pause();
+    fn unpause();
+    fn is_paused() -> bool;
+    fn get_kill_deadline() -> u64;
+    fn get_minimum_liquidity() -> u64;
+    fn get_amplification() -> u64;
+    fn get_fee() -> u64;
+    fn sync();
+    fn skim(recipient: Sender);
+    fn check_solvency() -> bool;
+    fn get_total_deposits(asset_id: ContractId) -> u64;
+    fn recover_token(asset_id: ContractId, recipient: Sender) -> u64;
+    fn set_kill_deadline(kill_deadline: u64);
+}
@@ -0,0 +1,188 @@
+contract;
+
+use ns_lib::NuclearSwap;
+
+use std::{
+    assert::assert,
+    chain::auth::*,
+    contract_id::ContractId,
+    hash::*,
+    logging::log,
+    result::*,
+    revert::revert,
+    storage::*,
+};
+
+storage {
+    owner: b256,
+    initialized: bool,
+    pool_count: u64,
+}
+
+pub struct PoolConfig {
+    assets: [ContractId; 2],
+    amplification: u64,
+    fee: u64,
+}
+
+pub struct PoolCreatedLog {
+    pool: ContractId,
+    assets: [ContractId; 2],
+    amplification: u64,
+    fee: u64,
+}
+
+// Storage delimited
+const S_POOLS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000000;
+const S_POOL_LIST: b256 = 0x0000000000000000000000000000000000000000000000000000000000000001;
+const S_POOL_ASSET_X: b256 = 0x0000000000000000000000000000000000000000000000000000000000000002;
+const S_POOL_ASSET_Y: b256 = 0x0000000000000000000000000000000000000000000000000000000000000003;
+const S_POOL_AMPLIFICATION: b256 = 0x0000000000000000000000000000000000000000000000000000000000000004;
+const S_POOL_FEE: b256 = 0x0000000000000000000000000000000000000000000000000000000000000005;
+
+// LP tokens every pool created here locks on its first mint
+const MINIMUM_LIQUIDITY = 1000;
+
+const ZERO_B256 = 0x0000000000000000000000000000000000000000000000000000000000000000;
+
+// Registry of stable_swap pools.
+// Contracts can't be deployed from a contract, so pools are deployed first and registered here.
+// Only the deployer can initialize a pool, so the factory checks each pool runs the pair, A and fee
+// it is registered with, under the factory owner, and keeps that config per pool.
+// Only the owner registers pools, which it deploys from the stable_swap bytecode, so nobody can
+// claim a pair with a contract of their own.
+abi PoolFactory {
+    fn initialize(owner: Sender);
+    fn get_owner() -> b256;
+    fn create_pool(pool: ContractId, assets: [ContractId; 2], amplification: u64, fee: u64);
+    fn get_pool(assets: [ContractId; 2]) -> ContractId;
+    fn get_pool_config(pool: ContractId) -> PoolConfig;
+    fn pool_count() -> u64;
+    fn pool_at(index: u64) -> ContractId;
+}
+
+impl PoolFactory for Contract {
+    fn initialize(owner: Sender) {
+        // Open to the first caller, so check `get_owner` before registering pools
+        assert(storage.initialized == false);
+
+        storage.owner = sender_key(owner);
+        storage.initialized = true;
+    }
+
+    fn get_owner() -> b256 {
+        storage.owner
+    }
+
+    fn create_pool(pool: ContractId, assets: [ContractId; 2], amplification: u64, fee: u64) {
+        only_owner();
+        assert(assets[0].into() != assets[1].into());
+
+        // One pool per pair, in either order
+        let pair_key = key_pools(assets[0].into(), assets[1].into());
+        assert(get::<b256>(pair_key) == ZERO_B256);
+
+        // A pool contract serves a single pair
+        assert(get::<u64>(key_pool_config(S_POOL_AMPLIFICATION, pool.into())) == 0);
+
+        // Every registered pool runs with the config stored here
+        let pool_contract = abi(NuclearSwap, pool.into());
+        let pool_assets = pool_contract.get_assets();
+        assert(pool_assets[0].into() == assets[0].into());
+        assert(pool_assets[1].into() == assets[1].into());
+        assert(pool_contract.get_amplification() == amplification);
+        assert(pool_contract.get_fee() == fee);
+        assert(pool_contract.get_minimum_liquidity() == MINIMUM_LIQUIDITY);
+        assert(pool_contract.get_owner() == storage.owner);
+
+        store(pair_key, pool.into());
+        store(key_pools(assets[1].into(), assets[0].into()), pool.into());
+
+        let count = storage.pool_count;
+        store(key_pool_list(count), pool.into());
+        storage.pool_count = count + 1;
+
+        store(key_pool_config(S_POOL_ASSET_X, pool.into()), assets[0].into());
+        store(key_pool_config(S_POOL_ASSET_Y, pool.into()), assets[1].into());
+        store(key_pool_config(S_POOL_AMPLIFICATION, pool.into()), amplification);
+        store(key_pool_config(S_POOL_FEE, pool.into()), fee);
+
+        log(PoolCreatedLog {
+            pool: pool,
+            assets: assets,
+            amplification: amplification,
+            fee: fee,
+        });
+    }
+
+    fn get_pool(assets: [ContractId; 2]) -> ContractId {
+        ~ContractId::from(get::<b256>(key_pools(assets[0].into(), assets[1].into())))
+    }
+
+    fn get_pool_config(pool: ContractId) -> PoolConfig {
+        let amplification = get::<u64>(key_pool_config(S_POOL_AMPLIFICATION, pool.into()));
+        assert(amplification > 0);
+
+        PoolConfig {
+            assets: [
+                ~ContractId::from(get::<b256>(key_pool_config(S_POOL_ASSET_X, pool.into()))),
+                ~ContractId::from(get::<b256>(key_pool_config(S_POOL_ASSET_Y, pool.into()))),
+            ],
+            amplification: amplification,
+            fee: get::<u64>(key_pool_config(S_POOL_FEE, pool.into())),
+        }
+    }
+
+    fn pool_count() -> u64 {
+        storage.pool_count
+    }
+
+    fn pool_at(index: u64) -> ContractId {
+        assert(index < storage.pool_count);
+        ~ContractId::from(get::<b256>(key_pool_list(index)))
+    }
+}
+
+// Revert unless the caller is the factory owner
+fn only_owner() {
+    assert(storage.initialized);
+    assert(sender_key(get_msg_sender_or_panic()) == storage.owner);
+}
+
+// Return the sender or panic
+fn get_msg_sender_or_panic() -> Sender {
+    let result: Result<Sender, AuthError> = msg_sender();
+    if result.is_err() {
+        revert(0);
+    };
+
+    result.unwrap()
+}
+
+// Unwrap an address or contract id into a b256
+fn sender_key(sender: Sender) -> b256 {
+    let mut ret = ZERO_B256;
+    if let Sender::Address(v) = sender {
+        ret = v.into();
+    } else if let Sender::ContractId(v) = sender {
+        ret = v.into();
+    };
+
+    ret
+}
+
+// Compute the storage slot of the pool registered for a pair
+fn key_pools(asset_x: b256, asset_y: b256) -> b256 {
+    let inner = sha256((asset_x, asset_y));
+    sha256((S_POOLS, inner))
+}
+
+// Compute the storage slot of the n-th registered pool
+fn key_pool_list(index: u64) -> b256 {
+    sha256((S_POOL_LIST, index))
+}
+
+// Compute the storage slot of a config field of a pool
+fn key_pool_config(field: b256, pool: b256) -> b256 {
+    sha256((field, pool))
+}
@@ -0,0 +1,266 @@
+use fuel_tx::{AssetId, ContractId};
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::test_helpers;
+use test_utils::{deploy_with_salt, launch_provider_and_get_wallet, launch_provider_and_get_wallets};
+
+// Load abi from json
+abigen!(MyContract, "out/debug/pool_factory-abi.json");
+abigen!(StableSwap, "../stable_swap/out/debug/stable_swap-abi.json");
+abigen!(TestToken, "../token_contract/out/debug/token_contract-abi.json");
+
+async fn get_contract_instance() -> (MyContract, ContractId, LocalWallet) {
+    // Launch a local network and deploy the contract
+    let wallet = launch_provider_and_get_wallet().await;
+
+    let id = Contract::deploy("./out/debug/pool_factory.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+
+    let instance = MyContract::new(id.to_string(), wallet.clone());
+    instance.initialize(Sender::Address(wallet.address())).call().await.unwrap();
+
+    (instance, id, wallet)
+}
+
+// Initialize a pool as the deployer, owned by the wallet, so the factory can register it
+async fn initialize_pool(wallet: &LocalWallet, pool_id: &ContractId, assets: [ContractId; 2], amplification: u64, fee: u64) {
+    StableSwap::new(pool_id.to_string(), wallet.clone())
+        .initialize(Sender::Address(wallet.address()), assets, amplification, fee, 1000)
+        .call()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn can_create_pools() {
+    let (_instance, _id, wallet) = get_contract_instance().await;
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+
+    // Two tokens and two pools
+    let token_a_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallet, 1).await;
+    let token_b_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallet, 2).await;
+    let pool_a_id = deploy_with_salt("../stable_swap/out/debug/stable_swap.bin", &wallet, 1).await;
+    let pool_b_id = deploy_with_salt("../stable_swap/out/debug/stable_swap.bin", &wallet, 2).await;
+    initialize_pool(&wallet, &pool_a_id, [native_asset_id.clone(), token_a_id.clone()], 1000, 300).await;
+    initialize_pool(&wallet, &pool_b_id, [native_asset_id.clone(), token_b_id.clone()], 500, 400).await;
+
+    _instance
+        .create_pool(pool_a_id.clone(), [native_asset_id.clone(), token_a_id.clone()], 1000, 300)
+        .set_contracts(&[pool_a_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    _instance
+        .create_pool(pool_b_id.clone(), [native_asset_id.clone(), token_b_id.clone()], 500, 400)
+        .set_contracts(&[pool_b_id.clone()])
+        .call()
+        .await
+        .unwrap();
+
+    // Discovery by index
+    let response = _instance.pool_count().call().await.unwrap();
+    assert_eq!(response.value, 2);
+    let response = _instance.pool_at(0).call().await.unwrap();
+    assert_eq!(response.value, pool_a_id);
+    let response = _instance.pool_at(1).call().await.unwrap();
+    assert_eq!(response.value, pool_b_id);
+    let result = _instance.pool_at(2).call().await;
+    assert!(result.is_err());
+
+    // Discovery by pair, in either order
+    let response = _instance
+        .get_pool([native_asset_id.clone(), token_a_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, pool_a_id);
+    let response = _instance
+        .get_pool([token_b_id.clone(), native_asset_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, pool_b_id);
+
+    // Unknown pairs return the zero id
+    let response = _instance
+        .get_pool([token_a_id.clone(), token_b_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, ContractId::new([0u8; 32]));
+
+    let config = _instance.get_pool_config(pool_b_id.clone()).call().await.unwrap().value;
+    assert_eq!(config.assets, [native_asset_id.clone(), token_b_id.clone()]);
+    assert_eq!(config.amplification, 500);
+    assert_eq!(config.fee, 400);
+
+    // The second pool trades its own token
+    let token_b_instance = TestToken::new(token_b_id.to_string(), wallet.clone());
+    token_b_instance.mint_coins(10000).call().await.unwrap();
+    token_b_instance
+        .transfer_coins_to_output(5000, token_b_id.clone(), wallet.address())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    let pool_b_instance = StableSwap::new(pool_b_id.to_string(), wallet.clone());
+    pool_b_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(5000), None))
+        .call()
+        .await
+        .unwrap();
+    pool_b_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(5000), Some(AssetId::from(*token_b_id))))
+        .call()
+        .await
+        .unwrap();
+    let result = pool_b_instance
+        .add_liquidity(1, 1000)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    // Factory pools lock 1000 LP tokens on the first mint
+    assert_eq!(result.value, 4000);
+}
+
+#[tokio::test]
+async fn cannot_create_duplicate_pool() {
+    let (_instance, _id, wallet) = get_contract_instance().await;
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallet, 1).await;
+    let pool_id = deploy_with_salt("../stable_swap/out/debug/stable_swap.bin", &wallet, 1).await;
+    let other_pool_id = deploy_with_salt("../stable_swap/out/debug/stable_swap.bin", &wallet, 2).await;
+    initialize_pool(&wallet, &pool_id, [native_asset_id.clone(), token_id.clone()], 1000, 300).await;
+    initialize_pool(&wallet, &other_pool_id, [token_id.clone(), native_asset_id.clone()], 1000, 300).await;
+
+    _instance
+        .create_pool(pool_id.clone(), [native_asset_id.clone(), token_id.clone()], 1000, 300)
+        .set_contracts(&[pool_id.clone()])
+        .call()
+        .await
+        .unwrap();
+
+    // Same pair with a fresh pool, in reversed order
+    let result = _instance
+        .create_pool(other_pool_id.clone(), [token_id.clone(), native_asset_id.clone()], 1000, 300)
+        .set_contracts(&[other_pool_id])
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Pair made of a single asset
+    let result = _instance
+        .create_pool(pool_id.clone(), [token_id.clone(), token_id], 1000, 300)
+        .set_contracts(&[pool_id])
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    let response = _instance.pool_count().call().await.unwrap();
+    assert_eq!(response.value, 1);
+}
+
+#[tokio::test]
+async fn only_owner_can_create_pools() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let id = Contract::deploy("./out/debug/pool_factory.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let _instance = MyContract::new(id.to_string(), wallet.clone());
+    let _other_instance = MyContract::new(id.to_string(), other_wallet.clone());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallet, 1).await;
+    let pool_id = deploy_with_salt("../stable_swap/out/debug/stable_swap.bin", &wallet, 1).await;
+    initialize_pool(&wallet, &pool_id, [native_asset_id.clone(), token_id.clone()], 1000, 300).await;
+
+    // Nothing can be registered before the factory has an owner
+    let result = _instance
+        .create_pool(pool_id.clone(), [native_asset_id.clone(), token_id.clone()], 1000, 300)
+        .set_contracts(&[pool_id.clone()])
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    _instance.initialize(Sender::Address(wallet.address())).call().await.unwrap();
+    let response = _other_instance.get_owner().call().await.unwrap();
+    assert_eq!(response.value, *wallet.address());
+    let result = _other_instance.initialize(Sender::Address(other_wallet.address())).call().await;
+    assert!(result.is_err());
+
+    // Anyone else trying to claim the pair is turned away
+    let result = _other_instance
+        .create_pool(pool_id.clone(), [native_asset_id.clone(), token_id.clone()], 1000, 300)
+        .set_contracts(&[pool_id.clone()])
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    _instance
+        .create_pool(pool_id.clone(), [native_asset_id.clone(), token_id.clone()], 1000, 300)
+        .set_contracts(&[pool_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    let response = _other_instance.get_pool([native_asset_id, token_id]).call().await.unwrap();
+    assert_eq!(response.value, pool_id);
+}
+
+#[tokio::test]
+async fn cannot_create_pool_with_other_config() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let id = Contract::deploy("./out/debug/pool_factory.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let _instance = MyContract::new(id.to_string(), wallet.clone());
+    _instance.initialize(Sender::Address(wallet.address())).call().await.unwrap();
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallet, 1).await;
+    let pool_id = deploy_with_salt("../stable_swap/out/debug/stable_swap.bin", &wallet, 1).await;
+    let other_pool_id = deploy_with_salt("../stable_swap/out/debug/stable_swap.bin", &wallet, 2).await;
+    initialize_pool(&wallet, &pool_id, [native_asset_id.clone(), token_id.clone()], 1000, 300).await;
+
+    // The pool runs another fee and another A than the ones it would be registered with
+    let result = _instance
+        .create_pool(pool_id.clone(), [native_asset_id.clone(), token_id.clone()], 1000, 400)
+        .set_contracts(&[pool_id.clone()])
+        .call()
+        .await;
+    assert!(result.is_err());
+    let result = _instance
+        .create_pool(pool_id.clone(), [native_asset_id.clone(), token_id.clone()], 500, 300)
+        .set_contracts(&[pool_id.clone()])
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // A pool owned by someone else than the factory owner
+    StableSwap::new(other_pool_id.to_string(), wallet.clone())
+        .initialize(Sender::Address(other_wallet.address()), [native_asset_id.clone(), token_id.clone()], 1000, 300, 1000)
+        .call()
+        .await
+        .unwrap();
+    let result = _instance
+        .create_pool(other_pool_id.clone(), [native_asset_id.clone(), token_id.clone()], 1000, 300)
+        .set_contracts(&[other_pool_id])
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    let response = _instance.pool_count().call().await.unwrap();
+    assert_eq!(response.value, 0);
+}
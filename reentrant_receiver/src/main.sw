@@ -0,0 +1,85 @@
+contract;
+
+use ns_lib::{FlashLoanReceiver, FlashSwapReceiver, NuclearSwap};
+
+use std::{
+    block::*,
+    chain::auth::*,
+    context::{*, call_frames::*},
+    contract_id::ContractId,
+    result::*,
+    revert::revert,
+    storage::*,
+    token::*,
+};
+
+storage {
+    swap_payment: u64,
+    reenter: bool,
+}
+
+// Malicious borrower used to check the pool's reentrancy guard.
+// Both callbacks call back into the pool that is in the middle of lending to it.
+// Flash swaps are paid `swap_payment` of the input asset up front, so the pool is repaid in full
+// and only the guard stops the swap back when `reenter` is set.
+abi ReentrantBorrower {
+    fn set_swap_payment(amount: u64);
+    fn set_reenter(reenter: bool);
+}
+
+impl ReentrantBorrower for Contract {
+    fn set_swap_payment(amount: u64) {
+        storage.swap_payment = amount;
+    }
+
+    fn set_reenter(reenter: bool) {
+        storage.reenter = reenter;
+    }
+}
+
+impl FlashLoanReceiver for Contract {
+    fn on_flash_loan(asset_id: ContractId, amount: u64, fee: u64, data: b256) {
+        let pool = get_msg_sender_contract_or_panic();
+
+        // Deposit the loan as if it were our own: the pool's balance is restored, so without
+        // the guard the repayment check passes and the deposit can be withdrawn afterwards
+        abi(NuclearSwap, pool.into()).deposit {
+            coins: amount, asset_id: asset_id.into()
+        }();
+        force_transfer(fee, asset_id, pool);
+    }
+}
+
+impl FlashSwapReceiver for Contract {
+    fn on_flash_swap(asset_out: ContractId, amount_out: u64, asset_in: ContractId, data: b256) {
+        let pool = get_msg_sender_contract_or_panic();
+        force_transfer(storage.swap_payment, asset_in, pool);
+
+        // Swap the output straight back and hand the proceeds to the pool as well, so its
+        // balance of the input asset only grows
+        if storage.reenter {
+            let amount_in = abi(NuclearSwap, pool.into()).swap {
+                coins: amount_out, asset_id: asset_out.into()
+            }(amount_out, 0, height() + 1);
+            force_transfer(amount_in, asset_in, pool);
+        };
+    }
+}
+
+// Return the calling contract or panic
+fn get_msg_sender_contract_or_panic() -> ContractId {
+    let result: Result<Sender, AuthError> = msg_sender();
+    let mut ret = ~ContractId::from(0x0000000000000000000000000000000000000000000000000000000000000000);
+    if result.is_err() {
+        revert(0);
+    } else {
+        let unwrapped = result.unwrap();
+        if let Sender::ContractId(v) = unwrapped {
+            ret = v;
+        } else {
+            revert(0);
+        };
+    };
+
+    ret
+}
@@ -0,0 +1,35 @@
+use fuel_tx::ContractId;
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::test_helpers;
+
+// Load abi from json
+abigen!(MyContract, "out/debug/reentrant_receiver-abi.json");
+
+async fn get_contract_instance() -> (MyContract, ContractId) {
+    // Launch a local network and deploy the contract
+    let wallet = launch_provider_and_get_wallet().await;
+
+    let id = Contract::deploy("./out/debug/reentrant_receiver.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+
+    let instance = MyContract::new(id.to_string(), wallet);
+
+    (instance, id)
+}
+
+#[tokio::test]
+async fn can_get_contract_id() {
+    let (_instance, _id) = get_contract_instance().await;
+
+    // The attacks themselves are run against the pool in stable_swap/tests/harness.rs
+}
+
+#[tokio::test]
+async fn can_set_swap_payment() {
+    let (_instance, _id) = get_contract_instance().await;
+
+    _instance.set_swap_payment(1100).call().await.unwrap();
+    _instance.set_reenter(true).call().await.unwrap();
+}
@@ -0,0 +1,139 @@
+contract;
+
+use ns_lib::{check_deadline, NuclearSwap};
+
+use std::{
+    assert::assert,
+    block::*,
+    chain::auth::*,
+    context::{*, call_frames::*},
+    contract_id::ContractId,
+    result::*,
+    revert::revert,
+    token::*,
+};
+
+// Most pools a route can go through, routes are passed as fixed size arrays
+const MAX_HOPS = 2;
+
+// Routes a swap through up to MAX_HOPS stable_swap pools, A -> B on the first, B -> C on the next.
+// Only the first `hops` pools are used, so a single-hop swap leaves the second entry unused.
+// The input is the forwarded coins, slippage is checked once on the final output.
+abi Router {
+    fn swap_exact_in(pools: [ContractId; 2], hops: u64, amount_in: u64, min_out: u64, deadline: u64) -> u64;
+    fn swap_exact_out(pools: [ContractId; 2], hops: u64, amount_out: u64, max_in: u64, deadline: u64) -> u64;
+}
+
+impl Router for Contract {
+    fn swap_exact_in(pools: [ContractId; 2], hops: u64, amount_in: u64, min_out: u64, deadline: u64) -> u64 {
+        check_deadline(deadline);
+        assert(hops > 0 && hops <= MAX_HOPS);
+        assert(amount_in > 0);
+        assert(msg_amount() == amount_in);
+
+        let sender = get_msg_sender_or_panic();
+
+        // Intermediate hops accept any output, the end-to-end minimum is checked below
+        let mut asset = msg_asset_id();
+        let mut amount = amount_in;
+        let mut i = 0;
+        while i < hops {
+            let asset_next = other_asset(pools[i], asset);
+            amount = swap_on(pools[i], asset, amount, deadline);
+            asset = asset_next;
+            i = i + 1;
+        }
+
+        assert(amount >= min_out);
+        transfer_to_sender(amount, asset, sender);
+
+        amount
+    }
+
+    fn swap_exact_out(pools: [ContractId; 2], hops: u64, amount_out: u64, max_in: u64, deadline: u64) -> u64 {
+        check_deadline(deadline);
+        assert(hops > 0 && hops <= MAX_HOPS);
+        assert(amount_out > 0);
+        assert(msg_amount() == max_in);
+
+        let sender = get_msg_sender_or_panic();
+        let asset_in = msg_asset_id();
+
+        // Work backwards from the output to the input each hop needs
+        let mut amount_in = amount_out;
+        let mut i = hops;
+        while i > 0 {
+            i = i - 1;
+            amount_in = abi(NuclearSwap, pools[i].into()).get_dx(hop_asset(pools, asset_in, i + 1), amount_in);
+        }
+        assert(amount_in <= max_in);
+
+        let mut received = amount_in;
+        while i < hops {
+            received = swap_on(pools[i], hop_asset(pools, asset_in, i), received, deadline);
+            i = i + 1;
+        }
+        assert(received >= amount_out);
+
+        // Refund what wasn't needed and pay out
+        if max_in > amount_in {
+            transfer_to_sender(max_in - amount_in, asset_in, sender);
+        };
+        transfer_to_sender(received, hop_asset(pools, asset_in, hops), sender);
+
+        amount_in
+    }
+}
+
+// The asset going into pool `hop` of the route, or coming out of the route when `hop` is its length
+fn hop_asset(pools: [ContractId; 2], asset_in: ContractId, hop: u64) -> ContractId {
+    let mut ret = asset_in;
+    let mut i = 0;
+    while i < hop {
+        ret = other_asset(pools[i], ret);
+        i = i + 1;
+    }
+
+    ret
+}
+
+// Swap the router's coins on a pool, the pool sends the output back to the router
+fn swap_on(pool: ContractId, asset_in: ContractId, amount_in: u64, deadline: u64) -> u64 {
+    let pool_contract = abi(NuclearSwap, pool.into());
+    pool_contract.swap {
+        coins: amount_in, asset_id: asset_in.into()
+    }(amount_in, 0, deadline)
+}
+
+// The asset a pool pays out for the given input
+fn other_asset(pool: ContractId, asset_in: ContractId) -> ContractId {
+    let assets = abi(NuclearSwap, pool.into()).get_assets();
+
+    let mut ret = assets[0];
+    if assets[0].into() == asset_in.into() {
+        ret = assets[1];
+    } else {
+        assert(assets[1].into() == asset_in.into());
+    };
+
+    ret
+}
+
+// Return the sender, whether it is an address or a contract, or panic
+fn get_msg_sender_or_panic() -> Sender {
+    let result: Result<Sender, AuthError> = msg_sender();
+    if result.is_err() {
+        revert(0);
+    };
+
+    result.unwrap()
+}
+
+// Send coins to an address as an output, or straight into a contract's balance
+fn transfer_to_sender(amount: u64, asset_id: ContractId, to: Sender) {
+    if let Sender::Address(v) = to {
+        transfer_to_output(amount, asset_id, v);
+    } else if let Sender::ContractId(v) = to {
+        force_transfer(amount, asset_id, v);
+    };
+}
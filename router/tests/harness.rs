@@ -0,0 +1,177 @@
+use fuel_tx::{AssetId, ContractId};
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::test_helpers;
+use test_utils::{get_funded_token, get_pool_with_liquidity, get_wallet_balance, launch_provider_and_get_wallet};
+
+// Load abi from json
+abigen!(MyContract, "out/debug/router-abi.json");
+
+// Three tokens A, B and C, with an A/B pool and a B/C pool
+async fn get_contract_instance() -> (MyContract, LocalWallet, [ContractId; 3], [ContractId; 2]) {
+    // Launch a local network and deploy the contract
+    let wallet = launch_provider_and_get_wallet().await;
+
+    let id = Contract::deploy("./out/debug/router.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let instance = MyContract::new(id.to_string(), wallet.clone());
+
+    let token_a = get_funded_token(&[wallet.clone()], 1, 1_000_000).await;
+    let token_b = get_funded_token(&[wallet.clone()], 2, 1_000_000).await;
+    let token_c = get_funded_token(&[wallet.clone()], 3, 1_000_000).await;
+
+    let pool_ab = get_pool_with_liquidity(&wallet, 1, [token_a.clone(), token_b.clone()], 100_000).await;
+    let pool_bc = get_pool_with_liquidity(&wallet, 2, [token_b.clone(), token_c.clone()], 100_000).await;
+
+    (instance, wallet, [token_a, token_b, token_c], [pool_ab, pool_bc])
+}
+
+#[tokio::test]
+async fn can_swap_exact_in_across_two_pools() {
+    let (_instance, wallet, tokens, pools) = get_contract_instance().await;
+
+    let balance_before = get_wallet_balance(&wallet, &tokens[2]).await;
+
+    // A -> B -> C
+    let result = _instance
+        .swap_exact_in(pools.clone(), 2, 1000, 990, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert!(result.value >= 990);
+    assert!(result.value < 1000);
+
+    let balance_after = get_wallet_balance(&wallet, &tokens[2]).await;
+    assert_eq!(balance_after - balance_before, result.value);
+}
+
+#[tokio::test]
+async fn cannot_swap_exact_in_below_min_out() {
+    let (_instance, _wallet, tokens, pools) = get_contract_instance().await;
+
+    // Each hop alone would pass, but the route can't return more than it took in
+    let result = _instance
+        .swap_exact_in(pools.clone(), 2, 1000, 1000, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Expired deadline
+    let result = _instance
+        .swap_exact_in(pools.clone(), 2, 1000, 1, 1)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Forwarded coins have to match the input
+    let result = _instance
+        .swap_exact_in(pools.clone(), 2, 1000, 1, 1000)
+        .call_params(CallParameters::new(Some(500), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_swap_exact_out_across_two_pools() {
+    let (_instance, wallet, tokens, pools) = get_contract_instance().await;
+
+    let balance_in_before = get_wallet_balance(&wallet, &tokens[0]).await;
+    let balance_out_before = get_wallet_balance(&wallet, &tokens[2]).await;
+
+    // Ask for exactly 500 C, allowing up to 1000 A
+    let result = _instance
+        .swap_exact_out(pools.clone(), 2, 500, 1000, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+    let amount_in = result.value;
+    assert!(amount_in > 500);
+    assert!(amount_in < 1000);
+
+    // Only the used input left the wallet, and at least 500 C arrived
+    let balance_in_after = get_wallet_balance(&wallet, &tokens[0]).await;
+    let balance_out_after = get_wallet_balance(&wallet, &tokens[2]).await;
+    assert_eq!(balance_in_before - balance_in_after, amount_in);
+    assert!(balance_out_after - balance_out_before >= 500);
+
+    // Not enough input allowed for the requested output
+    let result = _instance
+        .swap_exact_out(pools.clone(), 2, 500, 400, 1000)
+        .call_params(CallParameters::new(Some(400), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(2)
+        .call()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_swap_on_a_single_pool() {
+    let (_instance, wallet, tokens, pools) = get_contract_instance().await;
+
+    let balance_before = get_wallet_balance(&wallet, &tokens[1]).await;
+
+    // A -> B, the second pool is ignored
+    let result = _instance
+        .swap_exact_in(pools.clone(), 1, 1000, 995, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert!(result.value >= 995);
+    assert!(result.value < 1000);
+
+    let balance_after = get_wallet_balance(&wallet, &tokens[1]).await;
+    assert_eq!(balance_after - balance_before, result.value);
+
+    // Ask for exactly 500 B, allowing up to 1000 A
+    let balance_before = get_wallet_balance(&wallet, &tokens[1]).await;
+    let result = _instance
+        .swap_exact_out(pools.clone(), 1, 500, 1000, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+    assert!(result.value > 500);
+    assert!(result.value < 510);
+    let balance_after = get_wallet_balance(&wallet, &tokens[1]).await;
+    assert!(balance_after - balance_before >= 500);
+
+    // A route needs at least one pool and no more than the array holds
+    let result = _instance
+        .swap_exact_in(pools.clone(), 0, 1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+    let result = _instance
+        .swap_exact_in(pools.clone(), 3, 1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*tokens[0]))))
+        .set_contracts(&pools)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+}
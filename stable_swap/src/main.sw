@@ -1,6 +1,19 @@
 contract;
 
-use ns_lib::abs; // needs to be added
+use ns_lib::{
+    abs,
+    check_deadline,
+    CircuitBreakerLimits,
+    CumulativePrices,
+    FlashLoanReceiver,
+    FlashSwapReceiver,
+    NuclearSwap,
+    PoolStats,
+    RateProvider,
+    RemoveLiquidityReturn,
+    SwapCommitment,
+    TwapPrices,
+}; // needs to be added
 
 use std::{
     address::*,
@@ -21,36 +34,174 @@ use std::{
 storage {
     totalSupply: u64,
     lp_token_supply: u64,
+    eth_price_cumulative: u64,
+    token_price_cumulative: u64,
+    price_last_height: u64,
+    observation_index: u64,
+    observation_count: u64,
+    last_price: u64,
+    ma_price: u64,
+    ma_last_height: u64,
+    ma_exp_time: u64,
+    owner: b256,
+    initialized: bool,
+    eth_id: b256,
+    token_id: b256,
+    amplification: u64,
+    fee: u64,
+    base_pool: b256,
+    paused: bool,
+    kill_deadline: u64,
+    locked: bool,
+    minimum_liquidity: u64,
+    offpeg_fee_multiplier: u64,
+    eth_rate_provider: b256,
+    token_rate_provider: b256,
+    eth_volume: u64,
+    token_volume: u64,
+    eth_fees: u64,
+    token_fees: u64,
+    swap_count: u64,
+    last_trade_height: u64,
+    max_trade_ratio: u64,
+    max_price_deviation: u64,
 }
 
-pub struct RemoveLiquidityReturn {
+pub struct Logger {
+    amount: u64,
+}
+
+// The depositor's deposit key, an address or a contract id. Contracts can deposit too,
+// so this is no longer an Address.
+pub struct SenderLog {
+    sender: b256,
+}
+
+pub struct DepositTransferLog {
+    from: b256,
+    to: b256,
+    asset_id: ContractId,
+    amount: u64,
+}
+
+pub struct FlashLoanLog {
+    receiver: ContractId,
+    asset_id: ContractId,
+    amount: u64,
+    fee: u64,
+}
+
+pub struct FlashSwapLog {
+    receiver: ContractId,
+    asset_out: ContractId,
+    amount_out: u64,
+    asset_in: ContractId,
+    amount_in: u64,
+}
+
+pub struct OperatorApprovalLog {
+    owner: b256,
+    operator: b256,
+    asset_id: ContractId,
+    amount: u64,
+}
+
+pub struct PauseLog {
+    paused: bool,
+}
+
+pub struct SyncLog {
+    eth_reserve: u64,
+    token_reserve: u64,
+}
+
+pub struct SkimLog {
+    recipient: b256,
     eth_amount: u64,
     token_amount: u64,
 }
 
-pub struct Logger {
+pub struct ReferralFeeLog {
+    referrer: b256,
+    asset_id: ContractId,
     amount: u64,
 }
 
-pub struct SenderLog {
-    sender: Address,
+// Logged right before a trade is reverted by the circuit breaker
+pub enum CircuitBreakerError {
+    // The trade takes more than `max_trade_ratio` of the output reserve
+    TradeTooLarge: (),
+    // The trade leaves the spot price more than `max_price_deviation` away from the EMA
+    PriceDeviationTooLarge: (),
+}
+
+pub struct RecoverTokenLog {
+    asset_id: ContractId,
+    recipient: b256,
+    amount: u64,
 }
 
 // Token ID of Ether
 const ETH_ID = 0x0000000000000000000000000000000000000000000000000000000000000000;
 
 // Contract ID of the token on the other side of the pool.
-// Modify at compile time for different pool.
+// Modify at compile time for different pool, or set the pair with `initialize`.
 const TOKEN_ID = 0xb72c566e5a9f69c98298a04d70a38cb32baca4d9b280da8590e0314fb00c59e0;
 
+// Address allowed to initialize the pool, the key it is deployed with.
+// Set at compile time, the default is the deployer key of the test harness (test_utils::DEPLOYER_SECRET_KEY).
+const DEPLOYER = 0x09c0b2d1a486c439a87bcba6b46a7a1a23f3897cc83a94521a96da5c23bc58db;
+
+// Amplification coefficient, used until the pool is initialized with its own
+const DEFAULT_AMPLIFICATION = 1000;
+
+// Swap fee over a FEE_DENOMINATOR of 10^6, used until the pool is initialized with its own
+const DEFAULT_SWAP_FEE = 300;
+
 // Storage delimited
 const S_DEPOSITS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000000;
+const S_ALLOWANCES: b256 = 0x0000000000000000000000000000000000000000000000000000000000000001;
+const S_OBSERVATION_HEIGHTS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000002;
+const S_OBSERVATION_ETH_CUMULATIVES: b256 = 0x0000000000000000000000000000000000000000000000000000000000000003;
+const S_OBSERVATION_TOKEN_CUMULATIVES: b256 = 0x0000000000000000000000000000000000000000000000000000000000000004;
+const S_TOTAL_DEPOSITS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000005;
+const S_COMMITMENT_OWNERS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000006;
+const S_COMMITMENT_ASSETS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000007;
+const S_COMMITMENT_AMOUNTS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000008;
+const S_COMMITMENT_HEIGHTS: b256 = 0x0000000000000000000000000000000000000000000000000000000000000009;
+
+const ZERO_B256 = 0x0000000000000000000000000000000000000000000000000000000000000000;
 
-/// Minimum ETH liquidity to open a pool.
-const MINIMUM_LIQUIDITY = 1; //A more realistic value would be 1000000000;
+// Number of price observations kept in the TWAP ring buffer
+const OBSERVATION_CARDINALITY = 32;
 
-//SWAP_FEE: u64,
-const SWAP_FEE = 1;
+// Prices are expressed with 6 decimals
+const PRICE_PRECISION = 1000000;
+
+// ln(2) with PRICE_PRECISION decimals
+const LN_2 = 693147;
+
+// Half-time of the EMA price oracle in blocks, used until the owner sets one
+const DEFAULT_MA_EXP_TIME = 600;
+
+// Blocks after its commitment during which a two-phase swap can be revealed, after that it can only be refunded
+const REVEAL_WINDOW = 20;
+
+// Blocks after initialization during which the owner can still pause the pool, about 60 days
+const KILL_DEADLINE_DT = 432000;
+
+/// LP tokens locked forever on the first mint, used until the pool is initialized with its own.
+const DEFAULT_MINIMUM_LIQUIDITY = 1; //A more realistic value would be 1000000000;
+
+// Flash loan fee, 0.09% over a FEE_DENOMINATOR of 10^6
+const FLASH_LOAN_FEE = 900;
+
+// Highest share of the swap fee a referrer can take, over a BPS_DENOMINATOR of 10^4
+const MAX_REFERRAL_FEE_BPS = 5000;
+const BPS_DENOMINATOR = 10000;
+
+// Highest fee the off-peg multiplier can raise the swap fee to, 50% over a FEE_DENOMINATOR of 10^6
+const MAX_FEE = 500000;
 
 // 2 because of 2 asset pool!
 const N = 2;
@@ -61,37 +212,498 @@ const N = 2;
 
 // const DECIMALS: u64 = 10**18;
 
-abi NuclearSwap {
-    fn get_balance(token: ContractId) -> u64;
-    fn get_balances(target: ContractId, asset_id: ContractId) -> u64;
-    fn deposit();
-    fn withdraw(amount: u64, asset_id: ContractId);
-    //fn getVirtualPrice() -> u64;
-    fn swap(dx: u64, minDy: u64) -> u64;
-    fn add_liquidity(min_liquidity: u64, deadline: u64) -> u64;
-    fn remove_liquidity(min_eth: u64, min_tokens: u64, deadline: u64) -> RemoveLiquidityReturn;
-    fn test_current_reserve(token_id: b256) -> u64;
-}
-
 impl NuclearSwap for Contract {
     fn test_current_reserve(token_id: b256) -> u64 {
         get::<u64>(token_id)
     }
 
+    fn initialize(owner: Sender, assets: [ContractId; 2], amplification: u64, fee: u64, minimum_liquidity: u64) {
+        lock();
+        // Fuel can't deploy a contract and call it in the same transaction, so only the deployer
+        // can set the owner and the pair, nobody can get in between
+        assert(get_msg_sender_key_or_panic() == DEPLOYER);
+        assert(storage.initialized == false);
+        // The pair can't change under existing liquidity, or under deposits made into the default pair
+        assert(storage.lp_token_supply == 0);
+        assert(get::<u64>(key_total_deposits(ETH_ID)) == 0);
+        assert(get::<u64>(key_total_deposits(TOKEN_ID)) == 0);
+        assert(assets[0].into() != assets[1].into());
+        assert(amplification > 0);
+        assert(fee < exp(10, 6));
+        assert(minimum_liquidity > 0);
+
+        storage.owner = sender_key(owner);
+        storage.eth_id = assets[0].into();
+        storage.token_id = assets[1].into();
+        storage.amplification = amplification;
+        storage.fee = fee;
+        storage.minimum_liquidity = minimum_liquidity;
+        storage.kill_deadline = height() + KILL_DEADLINE_DT;
+        storage.initialized = true;
+        unlock();
+    }
+
+    fn get_owner() -> b256 {
+        storage.owner
+    }
+
+    fn pause() {
+        lock();
+        only_owner();
+        // Past the kill deadline the pool can't be stopped anymore
+        assert(height() < storage.kill_deadline);
+
+        storage.paused = true;
+        log(PauseLog {
+            paused: true
+        });
+        unlock();
+    }
+
+    fn unpause() {
+        lock();
+        only_owner();
+
+        storage.paused = false;
+        log(PauseLog {
+            paused: false
+        });
+        unlock();
+    }
+
+    fn is_paused() -> bool {
+        storage.paused
+    }
+
+    fn get_minimum_liquidity() -> u64 {
+        minimum_liquidity()
+    }
+
+    fn get_amplification() -> u64 {
+        amplification()
+    }
+
+    fn get_fee() -> u64 {
+        swap_fee()
+    }
+
+    fn sync() {
+        lock();
+        only_owner();
+
+        // Whatever the pool holds beyond the users' deposits becomes the reserves
+        let eth_reserve = balance_of(contract_id(), ~ContractId::from(pool_eth_id())) - total_deposits(pool_eth_id());
+        let token_reserve = balance_of(contract_id(), ~ContractId::from(pool_token_id())) - total_deposits(pool_token_id());
+        store(pool_eth_id(), eth_reserve);
+        store(pool_token_id(), token_reserve);
+        save_last_price();
+
+        log(SyncLog {
+            eth_reserve: eth_reserve,
+            token_reserve: token_reserve,
+        });
+        unlock();
+    }
+
+    fn skim(recipient: Sender) {
+        lock();
+        // The excess is coins sent to the pool outside of its calls, the owner decides where they go
+        only_owner();
+        let eth_amount = excess_balance(pool_eth_id());
+        let token_amount = excess_balance(pool_token_id());
+
+        if eth_amount > 0 {
+            transfer_to_sender(eth_amount, ~ContractId::from(pool_eth_id()), recipient);
+        };
+        if token_amount > 0 {
+            transfer_to_sender(token_amount, ~ContractId::from(pool_token_id()), recipient);
+        };
+
+        log(SkimLog {
+            recipient: sender_key(recipient),
+            eth_amount: eth_amount,
+            token_amount: token_amount,
+        });
+        unlock();
+    }
+
+    fn check_solvency() -> bool {
+        // All fees are credited to the reserves, there is no separate admin fee balance
+        let eth_owed = get_current_reserve(pool_eth_id()) + total_deposits(pool_eth_id());
+        let token_owed = get_current_reserve(pool_token_id()) + total_deposits(pool_token_id());
+        assert(balance_of(contract_id(), ~ContractId::from(pool_eth_id())) >= eth_owed);
+        assert(balance_of(contract_id(), ~ContractId::from(pool_token_id())) >= token_owed);
+
+        true
+    }
+
+    fn get_total_deposits(asset_id: ContractId) -> u64 {
+        total_deposits(asset_id.into())
+    }
+
+    fn recover_token(asset_id: ContractId, recipient: Sender) -> u64 {
+        lock();
+        only_owner();
+        // Pool assets are covered by `skim`, and the locked LP tokens must stay put
+        assert(asset_id.into() != pool_eth_id() && asset_id.into() != pool_token_id());
+        assert(asset_id.into() != contract_id().into());
+
+        let amount = balance_of(contract_id(), asset_id);
+        assert(amount > 0);
+        transfer_to_sender(amount, asset_id, recipient);
+
+        log(RecoverTokenLog {
+            asset_id: asset_id,
+            recipient: sender_key(recipient),
+            amount: amount,
+        });
+        unlock();
+
+        amount
+    }
+
+    fn get_kill_deadline() -> u64 {
+        storage.kill_deadline
+    }
+
+    fn set_kill_deadline(kill_deadline: u64) {
+        lock();
+        only_owner();
+        // The owner can give up the right to pause early, never extend it
+        assert(kill_deadline < storage.kill_deadline);
+
+        storage.kill_deadline = kill_deadline;
+        unlock();
+    }
+
+    fn set_circuit_breaker(max_trade_ratio: u64, max_price_deviation: u64) {
+        lock();
+        only_owner();
+        assert(max_trade_ratio <= exp(10, 6) && max_price_deviation <= PRICE_PRECISION);
+
+        storage.max_trade_ratio = max_trade_ratio;
+        storage.max_price_deviation = max_price_deviation;
+        unlock();
+    }
+
+    fn get_circuit_breaker() -> CircuitBreakerLimits {
+        CircuitBreakerLimits {
+            max_trade_ratio: storage.max_trade_ratio,
+            max_price_deviation: storage.max_price_deviation,
+        }
+    }
+
+    fn set_offpeg_fee_multiplier(offpeg_fee_multiplier: u64) {
+        lock();
+        only_owner();
+        // A multiplier up to FEE_DENOMINATOR turns the dynamic fee off
+        assert(offpeg_fee_multiplier * swap_fee() <= MAX_FEE * exp(10, 6));
+
+        storage.offpeg_fee_multiplier = offpeg_fee_multiplier;
+        unlock();
+    }
+
+    fn get_offpeg_fee_multiplier() -> u64 {
+        storage.offpeg_fee_multiplier
+    }
+
+    fn dynamic_fee(i: u64, j: u64) -> u64 {
+        assert(i < N && j < N && i != j);
+        let xp = current_xp();
+
+        _dynamic_fee(xp[i], xp[j], swap_fee())
+    }
+
+    fn set_rate_provider(asset_id: ContractId, rate_provider: ContractId) {
+        lock();
+        only_owner();
+        // Changing the rates of a funded pool would move its D, so the oracles are fixed before
+        // the first mint. The zero id leaves the asset at a rate of 1.
+        assert(storage.lp_token_supply == 0);
+        // A metapool already prices the base pool's LP token at its virtual price
+        assert(asset_id.into() != storage.base_pool);
+
+        if index_of(asset_id.into()) == 0 {
+            storage.eth_rate_provider = rate_provider.into();
+        } else {
+            storage.token_rate_provider = rate_provider.into();
+        };
+        unlock();
+    }
+
+    fn get_rate_provider(asset_id: ContractId) -> ContractId {
+        let mut ret = storage.eth_rate_provider;
+        if index_of(asset_id.into()) == 1 {
+            ret = storage.token_rate_provider;
+        };
+
+        ~ContractId::from(ret)
+    }
+
+    fn get_rates() -> [u64; 2] {
+        rates()
+    }
+
+    fn set_ma_exp_time(ma_exp_time: u64) {
+        lock();
+        only_owner();
+        assert(ma_exp_time > 0);
+
+        // Bring the EMA up to date under the old half-time first
+        update_price_oracle();
+        storage.ma_exp_time = ma_exp_time;
+        unlock();
+    }
+
+    fn get_p() -> u64 {
+        let reserve_x = get_current_reserve(pool_eth_id());
+        let reserve_y = get_current_reserve(pool_token_id());
+        assert(reserve_x > 0 && reserve_y > 0);
+
+        current_token_price()
+    }
+
+    fn price_oracle() -> u64 {
+        current_ma_price()
+    }
+
+    fn spot_price(i: u64, j: u64) -> u64 {
+        _spot_price(i, j)
+    }
+
+    fn price_impact(i: u64, j: u64, dx: u64) -> u64 {
+        assert(dx > 0);
+        let spot = _spot_price(i, j);
+
+        // Simulate the swap on the curve, without the fee
+        let dy = _exchange(asset_of(i), dx);
+
+        // Shortfall of the effective rate against the spot rate, as a fraction of PRICE_PRECISION
+        let effective = (dy * PRICE_PRECISION) / dx;
+        let mut impact: u64 = 0;
+        if spot > effective {
+            impact = ((spot - effective) * PRICE_PRECISION) / spot;
+        };
+
+        impact
+    }
+
+    fn get_assets() -> [ContractId; 2] {
+        [~ContractId::from(pool_eth_id()), ~ContractId::from(pool_token_id())]
+    }
+
+    fn get_dy(asset_in: ContractId, dx: u64) -> u64 {
+        assert(asset_in.into() == pool_eth_id() || asset_in.into() == pool_token_id());
+        assert(dx > 0);
+
+        // Same computation as `swap`
+        let dy = _exchange(asset_in.into(), dx);
+        let fee: u64 = (dy * trade_fee(asset_in.into(), dx, dy)) / 1000000;
+        dy - fee
+    }
+
+    fn get_dx(asset_out: ContractId, dy: u64) -> u64 {
+        assert(asset_out.into() == pool_eth_id() || asset_out.into() == pool_token_id());
+        assert(dy > 0);
+
+        let j = index_of(asset_out.into());
+        let i = 1 - j;
+        let rates = rates();
+        let xp = current_xp();
+
+        // The fee depends on where the trade leaves the pool, estimate it from the trade without the fee
+        let dy_xp_no_fee = (dy * rates[j]) / PRICE_PRECISION + 1;
+        assert(dy_xp_no_fee + 1 < xp[j]);
+        let x_no_fee: u64 = _getY(j, i, xp[j] - dy_xp_no_fee - 1, xp);
+        let fee = _dynamic_fee((xp[i] + x_no_fee) / 2, xp[j] - dy_xp_no_fee / 2, swap_fee());
+
+        // Gross up the output by the fee `swap` takes from it, rounding up
+        let FEE_DENOMINATOR = exp(10, 6);
+        let dy_before_fee = (dy * FEE_DENOMINATOR) / (FEE_DENOMINATOR - fee) + 1;
+        let dy_xp = (dy_before_fee * rates[j]) / PRICE_PRECISION + 1;
+        assert(dy_xp + 1 < xp[j]);
+
+        // Solve the invariant for the input side, with the output side reduced
+        let x: u64 = _getY(j, i, xp[j] - dy_xp - 1, xp);
+
+        ((x - xp[i]) * PRICE_PRECISION) / rates[i] + 1
+    }
+
+    fn get_imbalance_fees(amounts: [u64; 2]) -> [u64; 2] {
+        assert(storage.lp_token_supply > 0);
+
+        let current_reserves = scale_by_rates([get_current_reserve(pool_eth_id()), get_current_reserve(pool_token_id())]);
+        let new_reserves = scale_by_rates([
+            get_current_reserve(pool_eth_id()) + amounts[0],
+            get_current_reserve(pool_token_id()) + amounts[1],
+        ]);
+
+        imbalance_fees(current_reserves, new_reserves, _getD(current_reserves), _getD(new_reserves))
+    }
+
+    fn get_virtual_price() -> u64 {
+        let total_liquidity = storage.lp_token_supply;
+        let mut ret: u64 = 0;
+        if total_liquidity > 0 {
+            ret = (_getD(current_xp()) * PRICE_PRECISION) / total_liquidity;
+        };
+
+        ret
+    }
+
+    fn set_base_pool(base_pool: ContractId) {
+        lock();
+        only_owner();
+        // The second asset of a metapool is the base pool's LP token
+        assert(pool_token_id() == base_pool.into());
+        assert(storage.lp_token_supply == 0);
+        assert(storage.token_rate_provider == ZERO_B256);
+
+        storage.base_pool = base_pool.into();
+        unlock();
+    }
+
+    fn exchange_underlying(asset_out: ContractId, min_dy: u64, deadline: u64) -> u64 {
+        lock();
+        check_deadline(deadline);
+        when_not_paused();
+        let base_pool = storage.base_pool;
+        assert(base_pool != ZERO_B256);
+
+        let asset_in = msg_asset_id();
+        let amount_in = msg_amount();
+        let sender = get_msg_sender_or_panic();
+        assert(amount_in > 0);
+        update_price_oracle();
+
+        let base = abi(NuclearSwap, base_pool);
+        let underlying = base.get_assets();
+
+        // The base pool calls run under the caller's deadline. The caller's minimum bounds the last
+        // leg of the route, the earlier legs only feed it within this transaction.
+        let mut dy: u64 = 0;
+        if asset_in.into() == pool_eth_id() {
+            // Meta asset in: swap it for base LP, then take the LP apart into asset_out
+            let mut lp_amount = _exchange(pool_eth_id(), amount_in);
+            let fee = (lp_amount * trade_fee(pool_eth_id(), amount_in, lp_amount)) / 1000000;
+            lp_amount = lp_amount - fee;
+            record_swap(pool_eth_id(), amount_in, fee);
+            check_trade_size(pool_token_id(), lp_amount);
+            add_reserve(pool_eth_id(), amount_in);
+            remove_reserve(pool_token_id(), lp_amount);
+            check_price_deviation();
+
+            let removed = base.remove_liquidity {
+                coins: lp_amount, asset_id: base_pool
+            }(1, 1, deadline);
+
+            // Swap the other underlying coin into asset_out on the base pool, for whatever the
+            // caller's minimum still asks for on top of the coins already taken out
+            if asset_out.into() == underlying[0].into() {
+                let swapped = base.swap {
+                    coins: removed.token_amount, asset_id: underlying[1].into()
+                }(removed.token_amount, remaining_min(min_dy, removed.eth_amount), deadline);
+                dy = removed.eth_amount + swapped;
+            } else {
+                assert(asset_out.into() == underlying[1].into());
+                let swapped = base.swap {
+                    coins: removed.eth_amount, asset_id: underlying[0].into()
+                }(removed.eth_amount, remaining_min(min_dy, removed.token_amount), deadline);
+                dy = removed.token_amount + swapped;
+            };
+        } else {
+            // Underlying coin in: add it to the base pool, then swap the LP for the meta asset
+            assert(asset_out.into() == pool_eth_id());
+
+            let lp_amount = add_underlying(base_pool, underlying, asset_in, amount_in, sender, deadline);
+            dy = _exchange(pool_token_id(), lp_amount);
+            let fee = (dy * trade_fee(pool_token_id(), lp_amount, dy)) / 1000000;
+            dy = dy - fee;
+            record_swap(pool_token_id(), lp_amount, fee);
+            check_trade_size(pool_eth_id(), dy);
+            add_reserve(pool_token_id(), lp_amount);
+            remove_reserve(pool_eth_id(), dy);
+            check_price_deviation();
+        };
+        assert(dy >= min_dy);
+
+        save_last_price();
+
+        transfer_to_sender(dy, asset_out, sender);
+        unlock();
+
+        dy
+    }
+
+    fn get_cumulative_prices() -> CumulativePrices {
+        current_cumulative_prices()
+    }
+
+    fn get_twap(window: u64) -> TwapPrices {
+        assert(window > 0);
+
+        let current = current_cumulative_prices();
+        assert(current.height >= window);
+        let target_height = current.height - window;
+
+        // Walk back from the newest observation to the first one at least `window` blocks old
+        let count = storage.observation_count;
+        let mut index = storage.observation_index;
+        let mut found = false;
+        let mut observed_height: u64 = 0;
+        let mut observed_eth_cumulative: u64 = 0;
+        let mut observed_token_cumulative: u64 = 0;
+        let mut i = 0;
+        while i < count && found == false {
+            index = (index + OBSERVATION_CARDINALITY - 1) % OBSERVATION_CARDINALITY;
+            observed_height = get::<u64>(key_observation(S_OBSERVATION_HEIGHTS, index));
+            if observed_height <= target_height {
+                observed_eth_cumulative = get::<u64>(key_observation(S_OBSERVATION_ETH_CUMULATIVES, index));
+                observed_token_cumulative = get::<u64>(key_observation(S_OBSERVATION_TOKEN_CUMULATIVES, index));
+                found = true;
+            };
+            i = i + 1;
+        }
+        assert(found);
+
+        let elapsed = current.height - observed_height;
+        TwapPrices {
+            eth_price: (current.eth_price_cumulative - observed_eth_cumulative) / elapsed,
+            token_price: (current.token_price_cumulative - observed_token_cumulative) / elapsed,
+        }
+    }
+
+    fn get_stats() -> PoolStats {
+        PoolStats {
+            eth_volume: storage.eth_volume,
+            token_volume: storage.token_volume,
+            eth_fees: storage.eth_fees,
+            token_fees: storage.token_fees,
+            swap_count: storage.swap_count,
+            last_trade_height: storage.last_trade_height,
+        }
+    }
+
     fn get_balance(token: ContractId) -> u64 {
-        let sender = get_msg_sender_address_or_panic();
+        let sender = get_msg_sender_key_or_panic();
         let key = key_deposits(sender, token.into());
         get::<u64>(key)
     }
 
+    fn get_deposit(owner: Sender, asset_id: ContractId) -> u64 {
+        get::<u64>(key_deposits(sender_key(owner), asset_id.into()))
+    }
+
     fn get_balances(target: ContractId, asset_id: ContractId) -> u64 {
         balance_of(target, asset_id)
     }
 
     fn deposit() {
-        assert(msg_asset_id().into() == ETH_ID || msg_asset_id().into() == TOKEN_ID);
+        lock();
+        when_not_paused();
+        assert(msg_asset_id().into() == pool_eth_id() || msg_asset_id().into() == pool_token_id());
 
-        let sender = get_msg_sender_address_or_panic();
+        let sender = get_msg_sender_key_or_panic();
 
         let key = key_deposits(sender, msg_asset_id().into());
         let total_amount = get::<u64>(key) + msg_amount();
@@ -106,22 +718,100 @@ impl NuclearSwap for Contract {
         });
 
         store(key, total_amount);
+        add_total_deposits(msg_asset_id().into(), msg_amount());
+        unlock();
     }
 
     fn withdraw(amount: u64, asset_id: ContractId) {
-        assert(asset_id.into() == ETH_ID || asset_id.into() == TOKEN_ID);
+        lock();
+        assert(asset_id.into() == pool_eth_id() || asset_id.into() == pool_token_id());
 
-        let sender = get_msg_sender_address_or_panic();
+        let sender = get_msg_sender_or_panic();
 
         // Getting the specific token balance for a specific sender
-        let key = key_deposits(sender, asset_id.into());
+        let key = key_deposits(sender_key(sender), asset_id.into());
         let deposited_amount = get::<u64>(key);
         assert(deposited_amount >= amount);
 
         let new_amount = deposited_amount - amount;
         store(key, new_amount);
+        remove_total_deposits(asset_id.into(), amount);
 
-        transfer_to_output(amount, asset_id, sender)
+        transfer_to_sender(amount, asset_id, sender);
+        unlock();
+    }
+
+    fn withdraw_all() {
+        lock();
+        let sender = get_msg_sender_or_panic();
+
+        // Empty both sides of the caller's ledger, skipping assets with nothing deposited
+        let eth_key = key_deposits(sender_key(sender), pool_eth_id());
+        let eth_amount = get::<u64>(eth_key);
+        let token_key = key_deposits(sender_key(sender), pool_token_id());
+        let token_amount = get::<u64>(token_key);
+
+        store(eth_key, 0);
+        store(token_key, 0);
+        remove_total_deposits(pool_eth_id(), eth_amount);
+        remove_total_deposits(pool_token_id(), token_amount);
+
+        if eth_amount > 0 {
+            transfer_to_sender(eth_amount, ~ContractId::from(pool_eth_id()), sender);
+        };
+        if token_amount > 0 {
+            transfer_to_sender(token_amount, ~ContractId::from(pool_token_id()), sender);
+        };
+        unlock();
+    }
+
+    fn transfer_deposit(to: Sender, asset_id: ContractId, amount: u64) {
+        lock();
+        assert(asset_id.into() == pool_eth_id() || asset_id.into() == pool_token_id());
+
+        let from = get_msg_sender_key_or_panic();
+        move_deposit(from, sender_key(to), asset_id, amount);
+        unlock();
+    }
+
+    fn transfer_deposit_from(from: Sender, to: Sender, asset_id: ContractId, amount: u64) {
+        lock();
+        assert(asset_id.into() == pool_eth_id() || asset_id.into() == pool_token_id());
+
+        let operator = get_msg_sender_key_or_panic();
+        let from = sender_key(from);
+
+        // Spend the allowance the owner granted to this operator
+        let allowance_key = key_allowances(from, operator, asset_id.into());
+        let allowance = get::<u64>(allowance_key);
+        assert(allowance >= amount);
+        store(allowance_key, allowance - amount);
+
+        move_deposit(from, sender_key(to), asset_id, amount);
+        unlock();
+    }
+
+    fn approve_operator(operator: Sender, asset_id: ContractId, amount: u64) {
+        lock();
+        assert(asset_id.into() == pool_eth_id() || asset_id.into() == pool_token_id());
+
+        let owner = get_msg_sender_key_or_panic();
+        let operator = sender_key(operator);
+
+        // Approvals overwrite the previous allowance, they do not add up
+        store(key_allowances(owner, operator, asset_id.into()), amount);
+
+        log(OperatorApprovalLog {
+            owner: owner,
+            operator: operator,
+            asset_id: asset_id,
+            amount: amount,
+        });
+        unlock();
+    }
+
+    fn get_allowance(owner: Sender, operator: Sender, asset_id: ContractId) -> u64 {
+        get::<u64>(key_allowances(sender_key(owner), sender_key(operator), asset_id.into()))
     }
 
     /*
@@ -137,157 +827,132 @@ impl NuclearSwap for Contract {
     }
     */
 
-    fn swap(dx: u64, minDy: u64) -> u64 {
-        let FEE_DENOMINATOR = exp(10,6);
-        let LIQUIDITY_FEE = (SWAP_FEE * N) / (4 * (N - 1));
-        
-        // assert(i != j);
-        let i = 0;
-        let j = 1;
-        // remove i and j
-
-        let asset_id = msg_asset_id().into();
-        let forwarded_amount = msg_amount();
-        let sender = get_msg_sender_address_or_panic();
-        let mut dy: u64 = 0;
-
-        assert(msg_asset_id().into() == ETH_ID || msg_asset_id().into() == TOKEN_ID);
-        if msg_asset_id().into() == ETH_ID {
-            // Getting current reserves of both tokens
-            let current_reserve_x = get_current_reserve(ETH_ID);
-            let current_reserve_y = get_current_reserve(TOKEN_ID);
-
-            log(Logger {
-                amount: current_reserve_x
-            });
-            log(Logger {
-                amount: current_reserve_y
-            });
-
-            // Get new token_in amount:
-            assert(dx >= 0);
-            let new_reserve_x = current_reserve_x + dx;
-
-            // Get current balances and store in xp:
-            let xp: [u64; 2] = [current_reserve_x, current_reserve_y];
-
-            // Computing new token_out amount:
-            let new_reserve_y: u64 = _getY(i, j, new_reserve_x, xp);
-
-            // Computing delta token_out:
-            // y0 must be >= y1, since x has increased
-            // -1 to round down
-            dy = (current_reserve_y - new_reserve_y - 1);
+    fn swap(dx: u64, minDy: u64, deadline: u64) -> u64 {
+        lock();
+        check_deadline(deadline);
+        let asset_in = forwarded_asset(dx);
+        let dy = execute_swap(asset_in, dx, minDy, get_msg_sender_or_panic(), ZERO_B256, 0);
+        unlock();
 
-            // Subtract fee from dy
-            let fee: u64 = (dy * 300) / 1000000;
-            dy = dy - fee;
-            assert(dy >= minDy);
-
-            add_reserve(ETH_ID, dx);
-            remove_reserve(TOKEN_ID, dy);
-
-            // Getting new reserves of both tokens
-            let new_reserve_x = get_current_reserve(ETH_ID);
-            let new_reserve_y = get_current_reserve(TOKEN_ID);
-
-            log(Logger {
-                amount: new_reserve_x
-            });
-            log(Logger {
-                amount: new_reserve_y
-            });
-            log(Logger {
-                amount: dy
-            });
+        dy
+    }
 
-            // dy
-        } else if msg_asset_id().into() == TOKEN_ID {
-            // Getting current reserves of both tokens
-            let current_reserve_x = get_current_reserve(TOKEN_ID);
-            let current_reserve_y = get_current_reserve(ETH_ID);
+    fn swap_with_referrer(dx: u64, min_dy: u64, referrer: Sender, referral_fee_bps: u64, deadline: u64) -> u64 {
+        lock();
+        check_deadline(deadline);
+        assert(referral_fee_bps <= MAX_REFERRAL_FEE_BPS);
+        // A cut credited to the zero key could never be withdrawn
+        assert(referral_fee_bps == 0 || sender_key(referrer) != ZERO_B256);
+        let asset_in = forwarded_asset(dx);
+        let dy = execute_swap(asset_in, dx, min_dy, get_msg_sender_or_panic(), sender_key(referrer), referral_fee_bps);
+        unlock();
 
-            log(Logger {
-                amount: current_reserve_x
-            });
-            log(Logger {
-                amount: current_reserve_y
-            });
+        dy
+    }
 
-            // Get new token_in amount:
-            assert(dx >= 0);
-            let new_reserve_x = current_reserve_x + dx;
+    fn commit_swap(hash: b256) {
+        lock();
+        when_not_paused();
+        let asset_in = msg_asset_id().into();
+        let amount = msg_amount();
+        assert(asset_in == pool_eth_id() || asset_in == pool_token_id());
+        assert(amount > 0);
+
+        // Commitments are kept per owner, so copying someone's pending hash doesn't block theirs.
+        // One commitment per owner and hash, a salt is never reused.
+        let owner = get_msg_sender_key_or_panic();
+        assert(get::<u64>(key_commitment(S_COMMITMENT_HEIGHTS, owner, hash)) == 0);
+
+        store(key_commitment(S_COMMITMENT_OWNERS, owner, hash), owner);
+        store(key_commitment(S_COMMITMENT_ASSETS, owner, hash), asset_in);
+        store(key_commitment(S_COMMITMENT_AMOUNTS, owner, hash), amount);
+        store(key_commitment(S_COMMITMENT_HEIGHTS, owner, hash), height());
+
+        // The input is held for its owner like a deposit until the swap is revealed or refunded,
+        // so `skim` doesn't mistake it for excess
+        add_total_deposits(asset_in, amount);
+        unlock();
+    }
 
-            // Get current balances and store in xp:
-            let xp: [u64; 2] = [current_reserve_x, current_reserve_y];
+    fn reveal_swap(asset_in: ContractId, dx: u64, min_dy: u64, salt: b256) -> u64 {
+        lock();
+        let hash = sha256((asset_in.into(), dx, min_dy, salt));
+        let sender = get_msg_sender_or_panic();
+        let commitment = get_commitment_or_panic(sender_key(sender), hash);
 
-            // Computing new token_out amount:
-            let new_reserve_y: u64 = _getY(i, j, new_reserve_x, xp);
+        // Not in the block it was committed in, and not once it can be refunded
+        assert(height() > commitment.height);
+        assert(height() <= commitment.height + REVEAL_WINDOW);
+        assert(commitment.asset_id.into() == asset_in.into() && commitment.amount == dx);
 
-            // Computing delta token_out:
-            // y0 must be >= y1, since x has increased
-            // -1 to round down
-            dy = (current_reserve_y - new_reserve_y - 1);
+        clear_commitment(sender_key(sender), hash);
+        remove_total_deposits(asset_in.into(), dx);
+        let dy = execute_swap(asset_in.into(), dx, min_dy, sender, ZERO_B256, 0);
+        unlock();
 
-            // Subtract fee from dy
-            let fee: u64 = (dy * 300) / 1000000;
-            dy = dy - fee;
-            assert(dy >= minDy);
+        dy
+    }
 
-            add_reserve(TOKEN_ID, dx);
-            remove_reserve(ETH_ID, dy);
+    fn refund_swap(hash: b256) -> u64 {
+        lock();
+        let sender = get_msg_sender_or_panic();
+        let commitment = get_commitment_or_panic(sender_key(sender), hash);
+        assert(height() > commitment.height + REVEAL_WINDOW);
 
-            // Getting new reserves of both tokens
-            let new_reserve_x = get_current_reserve(TOKEN_ID);
-            let new_reserve_y = get_current_reserve(ETH_ID);
+        clear_commitment(sender_key(sender), hash);
+        remove_total_deposits(commitment.asset_id.into(), commitment.amount);
+        transfer_to_sender(commitment.amount, commitment.asset_id, sender);
+        unlock();
 
-            log(Logger {
-                amount: new_reserve_x
-            });
-            log(Logger {
-                amount: new_reserve_y
-            });
-            log(Logger {
-                amount: dy
-            });
+        commitment.amount
+    }
 
-            // dy
-        }
-        dy
+    fn get_commitment(owner: Sender, hash: b256) -> SwapCommitment {
+        read_commitment(sender_key(owner), hash)
     }
 
     fn add_liquidity(min_liquidity: u64, deadline: u64) -> u64 {
+        lock();
+        when_not_paused();
+        // No coins are forwarded, so the call's asset id is the base asset whatever the pair.
+        // Pools of two tokens or of an LP token don't hold it, so there is no asset id to check.
         assert(msg_amount() == 0);
-        assert(deadline > height());
-        assert(msg_asset_id().into() == ETH_ID || msg_asset_id().into() == TOKEN_ID);
-
-        let FEE_DENOMINATOR = exp(10, 6);
-        let LIQUIDITY_FEE = (SWAP_FEE * N) / (4 * (N - 1));
+        check_deadline(deadline);
+        update_price_oracle();
 
-        let sender = get_msg_sender_address_or_panic();
+        let sender = get_msg_sender_or_panic();
         let total_liquidity = storage.lp_token_supply;
 
-        let eth_amount_key = key_deposits(sender, ETH_ID);
+        let eth_amount_key = key_deposits(sender_key(sender), pool_eth_id());
         let current_eth_amount = get::<u64>(eth_amount_key);
 
-        let token_amount_key = key_deposits(sender, TOKEN_ID);
+        let token_amount_key = key_deposits(sender_key(sender), pool_token_id());
         let current_token_amount = get::<u64>(token_amount_key);
 
         assert(current_eth_amount > 0);
 
+        // Clear user contract balances before anything is paid out
+        store(token_amount_key, 0);
+        store(eth_amount_key, 0);
+        remove_total_deposits(pool_token_id(), current_token_amount);
+        remove_total_deposits(pool_eth_id(), current_eth_amount);
+
+        // What goes back to the sender once the pool's state is settled
         let mut minted: u64 = 0;
+        let mut refund_eth: u64 = 0;
+        let mut refund_token: u64 = 0;
         if total_liquidity > 0 {
             assert(min_liquidity > 0);
 
-            let current_eth_reserve = get_current_reserve(ETH_ID);
-            let current_token_reserve = get_current_reserve(TOKEN_ID);
+            let current_eth_reserve = get_current_reserve(pool_eth_id());
+            let current_token_reserve = get_current_reserve(pool_token_id());
 
             let token_amount = (current_eth_amount * current_token_reserve) / current_eth_reserve;
             // let liquidity_minted = (current_eth_amount * total_liquidity) / current_eth_reserve;
 
             // Get current balances and store in xp:
             let current_reserves: [u64;
-            2] = [current_eth_reserve, current_token_reserve];
+            2] = scale_by_rates([current_eth_reserve, current_token_reserve]);
 
             // Calculating D, sum of balances in a perfectly balanced pool
             let current_d = _getD(current_reserves);
@@ -296,93 +961,94 @@ impl NuclearSwap for Contract {
             // if token ratio is incorrect, return user balances to contract
             if (current_token_amount >= token_amount) {
                 // Adding new tokens to reserves:
-                add_reserve(TOKEN_ID, token_amount);
-                add_reserve(ETH_ID, current_eth_amount);
+                add_reserve(pool_token_id(), token_amount);
+                add_reserve(pool_eth_id(), current_eth_amount);
 
                 // Calculating ideal LP token amount to mint and send:
-                let new_eth_reserve = get_current_reserve(ETH_ID);
-                let new_token_reserve = get_current_reserve(TOKEN_ID);
-                let new_reserves: [u64; 2] = [new_eth_reserve, new_token_reserve];
+                let new_eth_reserve = get_current_reserve(pool_eth_id());
+                let new_token_reserve = get_current_reserve(pool_token_id());
+                let new_reserves: [u64; 2] = scale_by_rates([new_eth_reserve, new_token_reserve]);
 
                 let new_d = _getD(new_reserves); // Calculating D, sum of balances in a perfectly balanced pool
 
-                let idealBalance_eth: u64 = (current_eth_reserve * new_d) / current_d;
-                let diff_eth: u64 = abs(new_eth_reserve, idealBalance_eth);
-                let net_new_eth_reserve: u64 = (LIQUIDITY_FEE * diff_eth) / FEE_DENOMINATOR;
-
-                let idealBalance_token: u64 = (current_token_reserve * new_d) / current_d;
-                let diff_token: u64 = abs(new_token_reserve, idealBalance_token);
-                let net_new_token_reserve: u64 = (LIQUIDITY_FEE * diff_token) / FEE_DENOMINATOR;
-
-                let net_new_reserves: [u64; 2] = [net_new_eth_reserve, net_new_token_reserve];
+                // D of the new balances net of the imbalance fees
+                let fees = imbalance_fees(current_reserves, new_reserves, current_d, new_d);
+                let net_new_reserves: [u64; 2] = [new_reserves[0] - fees[0], new_reserves[1] - fees[1]];
                 let net_new_d = _getD(net_new_reserves);
 
                 let liquidity_to_mint = ((net_new_d - current_d) * total_liquidity) / current_d;
 
                 assert(liquidity_to_mint >= min_liquidity);
 
-                // Minting LP tokens and transferring to sender:
+                // Minting LP tokens for the sender:
                 mint(liquidity_to_mint);
                 storage.lp_token_supply = total_liquidity + liquidity_to_mint;
-
-                transfer_to_output(liquidity_to_mint, contract_id(), sender);
-
-                // In case user sent more than correct ratio, deposit back extra tokens to contract
-                let token_extra = current_token_amount - token_amount;
-                if (token_extra > 0) {
-                    transfer_to_output(token_extra, ~ContractId::from(TOKEN_ID), sender);
-                }
                 minted = liquidity_to_mint;
+
+                // In case user sent more than correct ratio, send back extra tokens
+                refund_token = current_token_amount - token_amount;
             } else {
-                transfer_to_output(current_token_amount, ~ContractId::from(TOKEN_ID), sender);
-                transfer_to_output(current_eth_amount, ~ContractId::from(ETH_ID), sender);
-                minted = 0;
+                refund_token = current_token_amount;
+                refund_eth = current_eth_amount;
             }
         } else {
-            assert(current_eth_amount > MINIMUM_LIQUIDITY);
+            // Opening the pool takes both sides and more than the shares that get locked
+            let minimum_liquidity = minimum_liquidity();
+            assert(current_eth_amount > minimum_liquidity);
+            assert(current_token_amount > 0);
 
             let initial_liquidity = current_eth_amount;
 
             // Add funds to the reserve
-            add_reserve(TOKEN_ID, current_token_amount);
-            add_reserve(ETH_ID, current_eth_amount);
+            add_reserve(pool_token_id(), current_token_amount);
+            add_reserve(pool_eth_id(), current_eth_amount);
 
-            // Mint the LP token
+            // Mint the LP token. The first `minimum_liquidity` shares stay in the pool's own balance
+            // and can never be redeemed, so the share price can't be inflated from a dust supply.
             mint(initial_liquidity);
             storage.lp_token_supply = initial_liquidity;
-
-            // Transfering LP token to user balance
-            transfer_to_output(initial_liquidity, contract_id(), sender);
-            minted = initial_liquidity;
+            minted = initial_liquidity - minimum_liquidity;
 
             log(Logger {
-                amount: get_current_reserve(ETH_ID)
+                amount: get_current_reserve(pool_eth_id())
             });
             log(Logger {
-                amount: get_current_reserve(TOKEN_ID)
+                amount: get_current_reserve(pool_token_id())
             });
         }
 
-        // Clear user contract balances after finishing add / create liquidity
-        store(token_amount_key, 0);
-        store(eth_amount_key, 0);
+        save_last_price();
+
+        // Transfering LP token and any refunds to the sender
+        if minted > 0 {
+            transfer_to_sender(minted, contract_id(), sender);
+        };
+        if refund_token > 0 {
+            transfer_to_sender(refund_token, ~ContractId::from(pool_token_id()), sender);
+        };
+        if refund_eth > 0 {
+            transfer_to_sender(refund_eth, ~ContractId::from(pool_eth_id()), sender);
+        };
+        unlock();
 
         minted
     }
 
     fn remove_liquidity(min_eth: u64, min_tokens: u64, deadline: u64) -> RemoveLiquidityReturn {
+        lock();
         assert(msg_amount() > 0);
         assert(msg_asset_id().into() == (contract_id()).into());
-        assert(deadline > height());
+        check_deadline(deadline);
         assert(min_eth > 0 && min_tokens > 0);
+        update_price_oracle();
 
-        let sender = get_msg_sender_address_or_panic();
+        let sender = get_msg_sender_or_panic();
 
         let total_liquidity = storage.lp_token_supply;
         assert(total_liquidity > 0);
 
-        let eth_reserve = get_current_reserve(ETH_ID);
-        let token_reserve = get_current_reserve(TOKEN_ID);
+        let eth_reserve = get_current_reserve(pool_eth_id());
+        let token_reserve = get_current_reserve(pool_token_id());
         let eth_amount = (msg_amount() * eth_reserve) / total_liquidity;
         let token_amount = (msg_amount() * token_reserve) / total_liquidity;
 
@@ -392,18 +1058,111 @@ impl NuclearSwap for Contract {
         storage.lp_token_supply = total_liquidity - msg_amount();
 
         // Remove funds from the reserve
-        remove_reserve(TOKEN_ID, token_amount);
-        remove_reserve(ETH_ID, eth_amount);
+        remove_reserve(pool_token_id(), token_amount);
+        remove_reserve(pool_eth_id(), eth_amount);
+
+        save_last_price();
 
         // Send tokens back
-        transfer_to_output(eth_amount, ~ContractId::from(ETH_ID), sender);
-        transfer_to_output(token_amount, ~ContractId::from(TOKEN_ID), sender);
+        transfer_to_sender(eth_amount, ~ContractId::from(pool_eth_id()), sender);
+        transfer_to_sender(token_amount, ~ContractId::from(pool_token_id()), sender);
+        unlock();
 
         RemoveLiquidityReturn {
             eth_amount: eth_amount,
             token_amount: token_amount,
         }
     }
+
+    fn flash_loan(asset_id: ContractId, amount: u64, receiver: ContractId, data: b256) {
+        lock();
+        when_not_paused();
+        assert(asset_id.into() == pool_eth_id() || asset_id.into() == pool_token_id());
+        assert(amount > 0);
+
+        // Only pool reserves can be lent out, never the users' deposits
+        assert(amount <= get_current_reserve(asset_id.into()));
+        update_price_oracle();
+
+        let FEE_DENOMINATOR = exp(10, 6);
+        let fee = (amount * FLASH_LOAN_FEE) / FEE_DENOMINATOR;
+        let balance_before = balance_of(contract_id(), asset_id);
+
+        // Lend the funds and hand control over to the receiver
+        force_transfer(amount, asset_id, receiver);
+        let receiver_contract = abi(FlashLoanReceiver, receiver.into());
+        receiver_contract.on_flash_loan(asset_id, amount, fee, data);
+
+        // The receiver must have paid back the loan plus fee
+        let balance_after = balance_of(contract_id(), asset_id);
+        assert(balance_after >= balance_before + fee);
+
+        // The fee goes to the LPs
+        add_reserve(asset_id.into(), fee);
+        save_last_price();
+
+        log(FlashLoanLog {
+            receiver: receiver,
+            asset_id: asset_id,
+            amount: amount,
+            fee: fee,
+        });
+        unlock();
+    }
+
+    fn flash_swap(asset_out: ContractId, amount_out: u64, receiver: ContractId, data: b256) -> u64 {
+        lock();
+        when_not_paused();
+        assert(asset_out.into() == pool_eth_id() || asset_out.into() == pool_token_id());
+        assert(amount_out > 0);
+        assert(amount_out < get_current_reserve(asset_out.into()));
+        check_trade_size(asset_out.into(), amount_out);
+        update_price_oracle();
+
+        let mut asset_in = ~ContractId::from(pool_eth_id());
+        if asset_out.into() == pool_eth_id() {
+            asset_in = ~ContractId::from(pool_token_id());
+        };
+
+        let current_d = _getD(current_xp());
+        let balance_before = balance_of(contract_id(), asset_in);
+
+        // Send the output first, the receiver pays for it in the callback
+        force_transfer(amount_out, asset_out, receiver);
+        let receiver_contract = abi(FlashSwapReceiver, receiver.into());
+        receiver_contract.on_flash_swap(asset_out, amount_out, asset_in, data);
+
+        let balance_after = balance_of(contract_id(), asset_in);
+        assert(balance_after > balance_before);
+        let amount_in = balance_after - balance_before;
+
+        // The invariant must hold on the input net of the swap fee
+        let fee: u64 = (amount_in * trade_fee(asset_in.into(), amount_in, amount_out)) / 1000000;
+        add_reserve(asset_in.into(), amount_in - fee);
+        remove_reserve(asset_out.into(), amount_out);
+
+        let new_d = _getD(current_xp());
+        assert(new_d >= current_d);
+
+        // The fee stays in the pool for the LPs
+        add_reserve(asset_in.into(), fee);
+        // Unlike a swap, the fee is taken from the input
+        record_swap(asset_in.into(), amount_in, 0);
+        add_fees(asset_in.into(), fee);
+        check_price_deviation();
+        save_last_price();
+
+        log(FlashSwapLog {
+            receiver: receiver,
+            asset_out: asset_out,
+            amount_out: amount_out,
+            asset_in: asset_in,
+            amount_in: amount_in,
+        });
+        unlock();
+
+        amount_in
+    }
 }
 
 fn exp(base: u64, exponent: u64) -> u64 {
@@ -430,7 +1189,7 @@ fn _getYD(i: u64, xp: [u64;
 
     let mut s: u64 = 0;
     let mut c: u64 = d;
-    let A: u64 = (1000 * (exp(N, N - 1)));
+    let A: u64 = (amplification() * (exp(N, N - 1)));
     // following A needs to be replaced by commented A
     // let A: u64 = (1000 * N);
     let a: u64 = A * N;
@@ -469,7 +1228,7 @@ fn _getY(i: u64, j: u64, x: u64, xp: [u64;
     // following A needs to be replaced by commented A
     // XXX -> N = 2 should be dynamic
     let N: u64 = 2;
-    let A: u64 = (1000 * (exp(N, N - 1)));
+    let A: u64 = (amplification() * (exp(N, N - 1)));
     let a: u64 = A * N;
     let d: u64 = _getD(xp);
     // uint s;
@@ -515,17 +1274,13 @@ fn _getD(xp: [u64;
 2]) -> u64 {
     // N: Number of tokens
     // A: Amplification coefficient multiplied by N^(N-1)
-    let current_reserve_x = get_current_reserve(ETH_ID);
-    let current_reserve_y = get_current_reserve(TOKEN_ID);
 
     // XXX -> N = 2
     let N: u64 = 2;
 
-    let A: u64 = (1000 * (exp(N, N - 1)));
+    let A: u64 = (amplification() * (exp(N, N - 1)));
     let a: u64 = A * N;
     let mut i = 0;
-    let xp: [u64;
-    2] = [current_reserve_x, current_reserve_y];
     let mut s: u64 = xp[0] + xp[1];
     /*
     while i < N {
@@ -555,20 +1310,636 @@ fn _getD(xp: [u64;
     d // Revert("D didn't converge");
 }
 
-// Return the sender as an Address or panic
-// XXX -> Put in library
-fn get_msg_sender_address_or_panic() -> Address {
+// Marginal price of TOKEN in ETH at the given rate-scaled balances, from the derivative of the invariant
+fn _get_p(xp: [u64;
+2]) -> u64 {
+    // XXX -> N = 2
+    let N: u64 = 2;
+    let A: u64 = (amplification() * (exp(N, N - 1)));
+    let a: u64 = A * N;
+    let d: u64 = _getD(xp);
+
+    // dr = D^(N+1) / (N^N * x * y)
+    let mut dr: u64 = d / exp(N, N);
+    dr = (dr * d) / xp[0];
+    dr = (dr * d) / xp[1];
+
+    let xp0_a: u64 = a * xp[0];
+    ((xp0_a + (dr * xp[0]) / xp[1]) * PRICE_PRECISION) / (xp0_a + dr)
+}
+
+// Marginal price of TOKEN in ETH at the current reserves, in units of the assets themselves
+fn current_token_price() -> u64 {
+    let rates = rates();
+    (_get_p(current_xp()) * rates[1]) / rates[0]
+}
+
+// Marginal amount of asset j received per unit of asset i, with PRICE_PRECISION decimals.
+// Index 0 is ETH, index 1 is TOKEN.
+fn _spot_price(i: u64, j: u64) -> u64 {
+    assert(i < N && j < N && i != j);
+
+    let reserve_x = get_current_reserve(pool_eth_id());
+    let reserve_y = get_current_reserve(pool_token_id());
+    assert(reserve_x > 0 && reserve_y > 0);
+
+    // _get_p prices TOKEN in ETH, which is the rate for selling TOKEN
+    let token_price = current_token_price();
+    let mut ret = token_price;
+    if i == 0 {
+        ret = (PRICE_PRECISION * PRICE_PRECISION) / token_price;
+    };
+
+    ret
+}
+
+// Asset forwarded to a swap. Paying less than `dx` would credit the reserves with coins the pool
+// never got, and paying more would leave the payer's excess to whoever skims it.
+fn forwarded_asset(dx: u64) -> b256 {
+    assert(msg_amount() == dx);
+    msg_asset_id().into()
+}
+
+// Swap `dx` of `asset_in`, already in the pool's balance, for the other asset and send it to `sender`.
+// Shared by `swap`, `swap_with_referrer` and `reveal_swap`.
+// `referral_fee_bps` of the fee is credited to `referrer` instead of the LPs.
+fn execute_swap(asset_in: b256, dx: u64, min_dy: u64, sender: Sender, referrer: b256, referral_fee_bps: u64) -> u64 {
+    when_not_paused();
+    assert(asset_in == pool_eth_id() || asset_in == pool_token_id());
+    update_price_oracle();
+
+    let asset_out = other_asset(asset_in);
+
+    // Getting current reserves of both tokens
+    log(Logger {
+        amount: get_current_reserve(asset_in)
+    });
+    log(Logger {
+        amount: get_current_reserve(asset_out)
+    });
+
+    // Computing delta token_out and subtracting the fee
+    let mut dy = _exchange(asset_in, dx);
+    let fee: u64 = (dy * trade_fee(asset_in, dx, dy)) / 1000000;
+    dy = dy - fee;
+    assert(dy >= min_dy);
+    record_swap(asset_in, dx, fee);
+
+    // The referrer's cut of the fee leaves the reserves for their deposits, where `withdraw` pays it out
+    let referral_fee = (fee * referral_fee_bps) / BPS_DENOMINATOR;
+
+    check_trade_size(asset_out, dy + referral_fee);
+    add_reserve(asset_in, dx);
+    remove_reserve(asset_out, dy + referral_fee);
+    check_price_deviation();
+
+    if referral_fee > 0 {
+        let referral_key = key_deposits(referrer, asset_out);
+        store(referral_key, get::<u64>(referral_key) + referral_fee);
+        add_total_deposits(asset_out, referral_fee);
+
+        log(ReferralFeeLog {
+            referrer: referrer,
+            asset_id: ~ContractId::from(asset_out),
+            amount: referral_fee,
+        });
+    };
+
+    // Getting new reserves of both tokens
+    log(Logger {
+        amount: get_current_reserve(asset_in)
+    });
+    log(Logger {
+        amount: get_current_reserve(asset_out)
+    });
+    log(Logger {
+        amount: dy
+    });
+
+    save_last_price();
+
+    // Send the output to the caller, which can be a contract such as a router
+    transfer_to_sender(dy, ~ContractId::from(asset_out), sender);
+
+    dy
+}
+
+// Revert if `amount_out` is more than `max_trade_ratio` of the output reserve.
+// Has to run before the reserves are updated.
+fn check_trade_size(asset_out: b256, amount_out: u64) {
+    let max_trade_ratio = storage.max_trade_ratio;
+    if max_trade_ratio > 0 && amount_out * exp(10, 6) > get_current_reserve(asset_out) * max_trade_ratio {
+        trip_circuit_breaker(CircuitBreakerError::TradeTooLarge);
+    };
+}
+
+// Revert if the spot price after a trade is more than `max_price_deviation` away from the EMA.
+// The EMA moves at most once per block, so this also bounds how far trades in one block can push the price.
+fn check_price_deviation() {
+    let max_price_deviation = storage.max_price_deviation;
+    let ma_price = current_ma_price();
+    if max_price_deviation > 0 && ma_price > 0 {
+        let deviation = abs(current_token_price(), ma_price);
+        if deviation * PRICE_PRECISION > ma_price * max_price_deviation {
+            trip_circuit_breaker(CircuitBreakerError::PriceDeviationTooLarge);
+        };
+    };
+}
+
+fn trip_circuit_breaker(error: CircuitBreakerError) {
+    log(error);
+    revert(0);
+}
+
+// Add a trade to the pool's statistics. `fee` is taken in the other asset, as swaps take it from the output.
+fn record_swap(asset_in: b256, amount_in: u64, fee: u64) {
+    if asset_in == pool_eth_id() {
+        storage.eth_volume = storage.eth_volume + amount_in;
+    } else {
+        storage.token_volume = storage.token_volume + amount_in;
+    };
+    add_fees(other_asset(asset_in), fee);
+    storage.swap_count = storage.swap_count + 1;
+    storage.last_trade_height = height();
+}
+
+fn add_fees(asset_id: b256, fee: u64) {
+    if asset_id == pool_eth_id() {
+        storage.eth_fees = storage.eth_fees + fee;
+    } else {
+        storage.token_fees = storage.token_fees + fee;
+    };
+}
+
+// Output of the curve for `dx` of `asset_in`, before the fee, in units of the output asset
+fn _exchange(asset_in: b256, dx: u64) -> u64 {
+    let i = index_of(asset_in);
+    let j = 1 - i;
+    let rates = rates();
+    let xp = current_xp();
+
+    // Computing new token_out amount:
+    let x = xp[i] + (dx * rates[i]) / PRICE_PRECISION;
+    let y: u64 = _getY(i, j, x, xp);
+
+    // y0 must be >= y1, since x has increased
+    // -1 to round down
+    ((xp[j] - y - 1) * PRICE_PRECISION) / rates[j]
+}
+
+// Swap fee scaled up as the pair moves off peg, over a FEE_DENOMINATOR of 10^6.
+// 4 * xpi * xpj / (xpi + xpj)^2 is 1 for balanced reserves and falls towards 0 as they drift apart,
+// so the fee goes from `fee` at the peg up to `fee * offpeg_fee_multiplier / FEE_DENOMINATOR`.
+fn _dynamic_fee(xpi: u64, xpj: u64, fee: u64) -> u64 {
+    let FEE_DENOMINATOR = exp(10, 6);
+    let multiplier = storage.offpeg_fee_multiplier;
+
+    let mut ret = fee;
+    if multiplier > FEE_DENOMINATOR && xpi + xpj > 0 {
+        // Work with each side's share of the sum, the squares of the reserves overflow u64
+        let share_i = (xpi * FEE_DENOMINATOR) / (xpi + xpj);
+        let share_j = (xpj * FEE_DENOMINATOR) / (xpi + xpj);
+        let balance = (4 * share_i * share_j) / FEE_DENOMINATOR;
+
+        ret = (multiplier * fee) / (((multiplier - FEE_DENOMINATOR) * balance) / FEE_DENOMINATOR + FEE_DENOMINATOR);
+    };
+
+    ret
+}
+
+// Imbalance fee on each side for moving the balances from `old_xp` to `new_xp`, in xp units.
+// Each side's average over the old and new balances is compared against the average D per coin,
+// (D0 + D1) / 2N, so the fee is Curve's share of the swap fee at the peg and rises off it like the swap fee.
+fn imbalance_fees(old_xp: [u64; 2], new_xp: [u64; 2], old_d: u64, new_d: u64) -> [u64; 2] {
+    let d_per_coin = (old_d + new_d) / (2 * N);
+    [
+        imbalance_fee(old_xp[0], new_xp[0], old_d, new_d, d_per_coin),
+        imbalance_fee(old_xp[1], new_xp[1], old_d, new_d, d_per_coin),
+    ]
+}
+
+// Imbalance fee on one side, charged on how far its new balance is from its share of the new D
+fn imbalance_fee(old_x: u64, new_x: u64, old_d: u64, new_d: u64, d_per_coin: u64) -> u64 {
+    let FEE_DENOMINATOR = exp(10, 6);
+    // Curve's imbalance fee, a share of the pool's own swap fee
+    let LIQUIDITY_FEE = (swap_fee() * N) / (4 * (N - 1));
+
+    let ideal_balance = (old_x * new_d) / old_d;
+    let diff = abs(new_x, ideal_balance);
+    let fee_rate = _dynamic_fee((old_x + new_x) / 2, d_per_coin, LIQUIDITY_FEE);
+
+    (fee_rate * diff) / FEE_DENOMINATOR
+}
+
+// Fee for `dx` of `asset_in` trading for `dy` before the fee, taken at the average of the
+// reserves before and after the trade. Must run before the reserves are updated.
+fn trade_fee(asset_in: b256, dx: u64, dy: u64) -> u64 {
+    let i = index_of(asset_in);
+    let j = 1 - i;
+    let rates = rates();
+    let xp = current_xp();
+
+    let dx_xp = (dx * rates[i]) / PRICE_PRECISION;
+    let dy_xp = (dy * rates[j]) / PRICE_PRECISION;
+    _dynamic_fee(xp[i] + dx_xp / 2, xp[j] - dy_xp / 2, swap_fee())
+}
+
+// Rates the reserves are multiplied by before entering the invariant, with PRICE_PRECISION decimals.
+// In a metapool the base pool's LP token is worth its virtual price, an asset with a rate provider
+// is worth its current rate, anything else is worth 1.
+fn rates() -> [u64; 2] {
+    let mut token_rate = PRICE_PRECISION;
+    let base_pool = storage.base_pool;
+    if base_pool != ZERO_B256 {
+        token_rate = abi(NuclearSwap, base_pool).get_virtual_price();
+    } else {
+        token_rate = provider_rate(storage.token_rate_provider);
+    };
+
+    [provider_rate(storage.eth_rate_provider), token_rate]
+}
+
+// Current rate of a rate provider, or 1 when there is none
+fn provider_rate(rate_provider: b256) -> u64 {
+    let mut ret = PRICE_PRECISION;
+    if rate_provider != ZERO_B256 {
+        ret = abi(RateProvider, rate_provider).get_rate();
+        assert(ret > 0);
+    };
+
+    ret
+}
+
+fn scale_by_rates(reserves: [u64; 2]) -> [u64; 2] {
+    let rates = rates();
+    [(reserves[0] * rates[0]) / PRICE_PRECISION, (reserves[1] * rates[1]) / PRICE_PRECISION]
+}
+
+// Current reserves scaled by the rates, in ETH, TOKEN order
+fn current_xp() -> [u64; 2] {
+    scale_by_rates([get_current_reserve(pool_eth_id()), get_current_reserve(pool_token_id())])
+}
+
+// Split an underlying coin over both sides of the base pool and add it as liquidity.
+// Whatever doesn't fit the base pool's ratio goes back to the caller.
+fn add_underlying(base_pool: b256, underlying: [ContractId; 2], asset_in: ContractId, amount_in: u64, sender: Sender, deadline: u64) -> u64 {
+    let base = abi(NuclearSwap, base_pool);
+
+    let mut k = 0;
+    if asset_in.into() == underlying[1].into() {
+        k = 1;
+    } else {
+        assert(asset_in.into() == underlying[0].into());
+    };
+
+    // Swap part of the coin so both sides go in at the base reserve ratio. Swapping `swapped` at
+    // a price p needs (amount_in - swapped) / (swapped * p) = reserve_k / reserve_other, with p
+    // quoted by the base pool on the share the coin would get at 1:1.
+    let reserve_k = base.test_current_reserve(underlying[k].into());
+    let reserve_other = base.test_current_reserve(underlying[1 - k].into());
+    let estimate = (amount_in * reserve_other) / (reserve_k + reserve_other);
+    assert(estimate > 0);
+    let quoted = base.get_dy(asset_in, estimate);
+    let swapped = (amount_in * reserve_other) / (reserve_other + (reserve_k * quoted) / estimate);
+    let received = base.swap {
+        coins: swapped, asset_id: asset_in.into()
+    }(swapped, 0, deadline);
+
+    let mut amount_0 = amount_in - swapped;
+    let mut amount_1 = received;
+    if k == 1 {
+        amount_0 = received;
+        amount_1 = amount_in - swapped;
+    };
+
+    // Size the deposits to the exact ratio the base pool asks for after the swap
+    let reserve_0 = base.test_current_reserve(underlying[0].into());
+    let reserve_1 = base.test_current_reserve(underlying[1].into());
+    let mut deposit_0 = amount_0;
+    if (amount_0 * reserve_1) / reserve_0 > amount_1 {
+        deposit_0 = (amount_1 * reserve_0) / reserve_1;
+    };
+    let deposit_1 = (deposit_0 * reserve_1) / reserve_0;
+
+    base.deposit {
+        coins: deposit_0, asset_id: underlying[0].into()
+    }();
+    base.deposit {
+        coins: deposit_1, asset_id: underlying[1].into()
+    }();
+    let lp_amount = base.add_liquidity(1, deadline);
+    assert(lp_amount > 0);
+
+    if amount_0 > deposit_0 {
+        transfer_to_sender(amount_0 - deposit_0, underlying[0], sender);
+    };
+    if amount_1 > deposit_1 {
+        transfer_to_sender(amount_1 - deposit_1, underlying[1], sender);
+    };
+
+    lp_amount
+}
+
+// What a leg of a route still has to pay out for the route to reach `min_out`, given what it already holds
+fn remaining_min(min_out: u64, held: u64) -> u64 {
+    let mut ret = 0;
+    if min_out > held {
+        ret = min_out - held;
+    };
+
+    ret
+}
+
+// The asset a swap pays out for the given input
+fn other_asset(asset_in: b256) -> b256 {
+    let mut ret = pool_token_id();
+    if asset_in == pool_token_id() {
+        ret = pool_eth_id();
+    };
+
+    ret
+}
+
+// Map an asset id to its coin index, 0 is ETH and 1 is TOKEN
+fn index_of(asset_id: b256) -> u64 {
+    assert(asset_id == pool_eth_id() || asset_id == pool_token_id());
+
+    let mut ret = 0;
+    if asset_id == pool_token_id() {
+        ret = 1;
+    };
+
+    ret
+}
+
+// Map a coin index to its asset id, 0 is ETH and 1 is TOKEN
+fn asset_of(index: u64) -> b256 {
+    assert(index < N);
+
+    let mut ret = pool_eth_id();
+    if index == 1 {
+        ret = pool_token_id();
+    };
+
+    ret
+}
+
+// Add the prices of the blocks since the last update to the accumulators, record an observation
+// and move the EMA forward, at most once per block.
+// Has to run before reserves change, so the elapsed blocks are priced at the old reserves.
+fn update_price_oracle() {
+    let current_height = height();
+    if current_height > storage.price_last_height {
+        let current = current_cumulative_prices();
+        storage.eth_price_cumulative = current.eth_price_cumulative;
+        storage.token_price_cumulative = current.token_price_cumulative;
+        storage.price_last_height = current_height;
+
+        write_observation(current_height);
+    };
+
+    if current_height > storage.ma_last_height {
+        storage.ma_price = current_ma_price();
+        storage.ma_last_height = current_height;
+    };
+}
+
+// Remember the spot price after an operation, the EMA moves towards it in the following blocks
+fn save_last_price() {
+    let reserve_x = get_current_reserve(pool_eth_id());
+    let reserve_y = get_current_reserve(pool_token_id());
+    if reserve_x > 0 && reserve_y > 0 {
+        let price = current_token_price();
+        storage.last_price = price;
+
+        // The first trade seeds the EMA
+        if storage.ma_price == 0 {
+            storage.ma_price = price;
+            storage.ma_last_height = height();
+        };
+    };
+}
+
+// EMA of the spot price extrapolated to the current block, without writing it
+fn current_ma_price() -> u64 {
+    let ma_price = storage.ma_price;
+    let last_height = storage.ma_last_height;
+    let current_height = height();
+
+    let mut ret = ma_price;
+    if current_height > last_height && ma_price > 0 {
+        let mut ma_exp_time = storage.ma_exp_time;
+        if ma_exp_time == 0 {
+            ma_exp_time = DEFAULT_MA_EXP_TIME;
+        };
+
+        let alpha = _half_life_decay(current_height - last_height, ma_exp_time);
+        ret = (storage.last_price * (PRICE_PRECISION - alpha) + ma_price * alpha) / PRICE_PRECISION;
+    };
+
+    ret
+}
+
+// 2^(-elapsed / half_time) with PRICE_PRECISION decimals
+fn _half_life_decay(elapsed: u64, half_time: u64) -> u64 {
+    let halvings = elapsed / half_time;
+
+    let mut ret: u64 = 0;
+    // 2^-20 is below PRICE_PRECISION
+    if halvings < 20 {
+        // Remaining fraction through the Taylor series of e^-y, with y = ln(2) * remainder / half_time
+        let y: u64 = (LN_2 * (elapsed % half_time)) / half_time;
+        let mut term: u64 = PRICE_PRECISION;
+        let mut positive: u64 = PRICE_PRECISION;
+        let mut negative: u64 = 0;
+        let mut k: u64 = 1;
+        while k < 6 {
+            term = (term * y) / (PRICE_PRECISION * k);
+            if k % 2 == 1 {
+                negative = negative + term;
+            } else {
+                positive = positive + term;
+            };
+            k = k + 1;
+        }
+        ret = (positive - negative) / exp(2, halvings);
+    };
+
+    ret
+}
+
+// Accumulators extrapolated to the current block, without writing them
+fn current_cumulative_prices() -> CumulativePrices {
+    let current_height = height();
+    let mut eth_price_cumulative = storage.eth_price_cumulative;
+    let mut token_price_cumulative = storage.token_price_cumulative;
+
+    let reserve_x = get_current_reserve(pool_eth_id());
+    let reserve_y = get_current_reserve(pool_token_id());
+    if current_height > storage.price_last_height && reserve_x > 0 && reserve_y > 0 {
+        let elapsed = current_height - storage.price_last_height;
+        let token_price = current_token_price();
+        let eth_price = (PRICE_PRECISION * PRICE_PRECISION) / token_price;
+
+        eth_price_cumulative = eth_price_cumulative + eth_price * elapsed;
+        token_price_cumulative = token_price_cumulative + token_price * elapsed;
+    };
+
+    CumulativePrices {
+        eth_price_cumulative: eth_price_cumulative,
+        token_price_cumulative: token_price_cumulative,
+        height: current_height,
+    }
+}
+
+// Store the accumulators in the next slot of the observation ring buffer
+fn write_observation(observed_height: u64) {
+    let index = storage.observation_index;
+    store(key_observation(S_OBSERVATION_HEIGHTS, index), observed_height);
+    store(key_observation(S_OBSERVATION_ETH_CUMULATIVES, index), storage.eth_price_cumulative);
+    store(key_observation(S_OBSERVATION_TOKEN_CUMULATIVES, index), storage.token_price_cumulative);
+
+    storage.observation_index = (index + 1) % OBSERVATION_CARDINALITY;
+    if storage.observation_count < OBSERVATION_CARDINALITY {
+        storage.observation_count = storage.observation_count + 1;
+    };
+}
+
+// Compute the storage slot of a field of an owner's swap commitment
+fn key_commitment(field: b256, owner: b256, hash: b256) -> b256 {
+    sha256((field, owner, hash))
+}
+
+fn read_commitment(owner: b256, hash: b256) -> SwapCommitment {
+    SwapCommitment {
+        owner: get::<b256>(key_commitment(S_COMMITMENT_OWNERS, owner, hash)),
+        asset_id: ~ContractId::from(get::<b256>(key_commitment(S_COMMITMENT_ASSETS, owner, hash))),
+        amount: get::<u64>(key_commitment(S_COMMITMENT_AMOUNTS, owner, hash)),
+        height: get::<u64>(key_commitment(S_COMMITMENT_HEIGHTS, owner, hash)),
+    }
+}
+
+// A commitment of this owner that exists, which also means a reveal hashed to a committed hash
+fn get_commitment_or_panic(owner: b256, hash: b256) -> SwapCommitment {
+    let ret = read_commitment(owner, hash);
+    assert(ret.height > 0);
+
+    ret
+}
+
+fn clear_commitment(owner: b256, hash: b256) {
+    store(key_commitment(S_COMMITMENT_OWNERS, owner, hash), ZERO_B256);
+    store(key_commitment(S_COMMITMENT_ASSETS, owner, hash), ZERO_B256);
+    store(key_commitment(S_COMMITMENT_AMOUNTS, owner, hash), 0);
+    store(key_commitment(S_COMMITMENT_HEIGHTS, owner, hash), 0);
+}
+
+// Compute the storage slot of an observation field in the ring buffer
+fn key_observation(field: b256, index: u64) -> b256 {
+    sha256((field, index))
+}
+
+// Asset on the first side of the pool, ETH unless initialized with another pair
+fn pool_eth_id() -> b256 {
+    let mut ret = ETH_ID;
+    if storage.initialized {
+        ret = storage.eth_id;
+    };
+
+    ret
+}
+
+// Asset on the second side of the pool, TOKEN_ID unless initialized with another pair
+fn pool_token_id() -> b256 {
+    let mut ret = TOKEN_ID;
+    if storage.initialized {
+        ret = storage.token_id;
+    };
+
+    ret
+}
+
+fn amplification() -> u64 {
+    let mut ret = DEFAULT_AMPLIFICATION;
+    if storage.initialized {
+        ret = storage.amplification;
+    };
+
+    ret
+}
+
+fn minimum_liquidity() -> u64 {
+    let mut ret = DEFAULT_MINIMUM_LIQUIDITY;
+    if storage.initialized {
+        ret = storage.minimum_liquidity;
+    };
+
+    ret
+}
+
+// Swap fee over a FEE_DENOMINATOR of 10^6
+fn swap_fee() -> u64 {
+    let mut ret = DEFAULT_SWAP_FEE;
+    if storage.initialized {
+        ret = storage.fee;
+    };
+
+    ret
+}
+
+// Non-reentrant guard, taken at the top of every mutating ABI method and released at the end.
+// A revert anywhere in between rolls the flag back with the rest of the state.
+fn lock() {
+    assert(storage.locked == false);
+    storage.locked = true;
+}
+
+fn unlock() {
+    storage.locked = false;
+}
+
+// Revert while the owner has paused the pool. Withdrawals and removing liquidity are never gated.
+fn when_not_paused() {
+    assert(storage.paused == false);
+}
+
+// Revert unless the caller is the pool owner
+fn only_owner() {
+    assert(storage.initialized);
+    assert(get_msg_sender_key_or_panic() == storage.owner);
+}
+
+// Return the sender as a b256 key, whether it is an address or a contract
+fn get_msg_sender_key_or_panic() -> b256 {
+    sender_key(get_msg_sender_or_panic())
+}
+
+// Return the sender, whether it is an address or a contract, or panic
+fn get_msg_sender_or_panic() -> Sender {
     let result: Result<Sender, AuthError> = msg_sender();
-    let mut ret = ~Address::from(0x0000000000000000000000000000000000000000000000000000000000000000);
     if result.is_err() {
         revert(0);
-    } else {
-        let unwrapped = result.unwrap();
-        if let Sender::Address(v) = unwrapped {
-            ret = v;
-        } else {
-            revert(0);
-        };
+    };
+
+    result.unwrap()
+}
+
+// Send coins to an address as an output, or straight into a contract's balance
+fn transfer_to_sender(amount: u64, asset_id: ContractId, to: Sender) {
+    if let Sender::Address(v) = to {
+        transfer_to_output(amount, asset_id, v);
+    } else if let Sender::ContractId(v) = to {
+        force_transfer(amount, asset_id, v);
+    };
+}
+
+// Unwrap an address or contract id into the b256 used for storage keys
+fn sender_key(sender: Sender) -> b256 {
+    let mut ret = 0x0000000000000000000000000000000000000000000000000000000000000000;
+    if let Sender::Address(v) = sender {
+        ret = v.into();
+    } else if let Sender::ContractId(v) = sender {
+        ret = v.into();
     };
 
     ret
@@ -576,11 +1947,61 @@ fn get_msg_sender_address_or_panic() -> Address {
 
 // Compute the storage slot for an address's deposits.
 // XXX -> Put in library
-fn key_deposits(a: Address, asset_id: b256) -> b256 {
-    let inner = sha256((a.into(), asset_id));
+fn key_deposits(owner: b256, asset_id: b256) -> b256 {
+    let inner = sha256((owner, asset_id));
     sha256((S_DEPOSITS, inner))
 }
 
+// Compute the storage slot for the amount an operator may move out of an owner's deposits.
+fn key_allowances(owner: b256, operator: b256, asset_id: b256) -> b256 {
+    let inner = sha256((owner, operator, asset_id));
+    sha256((S_ALLOWANCES, inner))
+}
+
+// Move an internal deposit balance from one account to another
+fn move_deposit(from: b256, to: b256, asset_id: ContractId, amount: u64) {
+    let from_key = key_deposits(from, asset_id.into());
+    let from_amount = get::<u64>(from_key);
+    assert(from_amount >= amount);
+    store(from_key, from_amount - amount);
+
+    let to_key = key_deposits(to, asset_id.into());
+    store(to_key, get::<u64>(to_key) + amount);
+
+    log(DepositTransferLog {
+        from: from,
+        to: to,
+        asset_id: asset_id,
+        amount: amount,
+    });
+}
+
+// Compute the storage slot for the sum of all users' deposits of an asset.
+fn key_total_deposits(asset_id: b256) -> b256 {
+    sha256((S_TOTAL_DEPOSITS, asset_id))
+}
+
+fn total_deposits(asset_id: b256) -> u64 {
+    get::<u64>(key_total_deposits(asset_id))
+}
+
+fn add_total_deposits(asset_id: b256, amount: u64) {
+    let key = key_total_deposits(asset_id);
+    store(key, get::<u64>(key) + amount);
+}
+
+fn remove_total_deposits(asset_id: b256, amount: u64) {
+    let key = key_total_deposits(asset_id);
+    store(key, get::<u64>(key) - amount);
+}
+
+// Coins of a pool asset held beyond the reserves and the users' deposits.
+// Reverts on underflow, which means the pool owes more than it holds.
+fn excess_balance(asset_id: b256) -> u64 {
+    let balance = balance_of(contract_id(), ~ContractId::from(asset_id));
+    balance - get_current_reserve(asset_id) - total_deposits(asset_id)
+}
+
 // Return token reserve balance
 // XXX -> Put in library
 fn get_current_reserve(token_id: b256) -> u64 {
@@ -1,11 +1,24 @@
-use fuel_tx::{AssetId, ContractId};
+use fuel_tx::{Address, AssetId, ContractId};
 use fuels_abigen_macro::abigen;
+use fuels::contract::contract::{CallResponse, ContractCallHandler};
+use fuels::core::{errors::Error, Detokenize};
 use fuels::prelude::*;
 use fuels::test_helpers;
+use std::fmt::Debug;
+use tai64::Tai64;
+use test_utils::{deploy_with_salt, get_funded_token, get_wallet_balance, launch_provider_and_get_wallet, launch_provider_and_get_wallets, produce_blocks};
+
+mod utils;
+
+// Fuel block timestamps are TAI64, deadlines at or above this offset are read as timestamps
+const TAI64_OFFSET: u64 = 1 << 62;
 
 // Load abi from json
 abigen!(MyContract, "out/debug/stable_swap-abi.json");
 abigen!(TestToken,"../token_contract/out/debug/token_contract-abi.json");
+abigen!(FlashLoanReceiverContract, "../flash_loan_receiver/out/debug/flash_loan_receiver-abi.json");
+abigen!(MockRateProvider, "../mock_rate_provider/out/debug/mock_rate_provider-abi.json");
+abigen!(ReentrantReceiverContract, "../reentrant_receiver/out/debug/reentrant_receiver-abi.json");
 
 async fn get_contract_instance() -> (MyContract, ContractId, TestToken, ContractId) {
     // Launch a local network and deploy the contract
@@ -31,6 +44,53 @@ async fn get_contract_instance() -> (MyContract, ContractId, TestToken, Contract
     (swap_contract_instance, swap_contract_id, token_contract_instance, token_contract_id)
 }
 
+// Deploy the pool and token, and seed the pool with `liquidity` of both assets at 1:1
+async fn get_pool_with_liquidity(wallet: &LocalWallet, liquidity: u64) -> (MyContract, ContractId, TestToken, ContractId) {
+    // Fund the wallet with alt tokens, and leave as many again in the token contract
+    let token_contract_id = get_funded_token(&[wallet.clone()], 0, liquidity * 5).await;
+    let token_contract_instance = TestToken::new(token_contract_id.to_string(), wallet.clone());
+    token_contract_instance.mint_coins(liquidity * 5).call().await.unwrap();
+
+    // Pair the pool with this token instance, owned by the wallet, and open it
+    let swap_contract_id = test_utils::get_pool_with_liquidity(
+        wallet,
+        0,
+        [ContractId::new(*NATIVE_ASSET_ID), token_contract_id.clone()],
+        liquidity,
+    )
+    .await;
+    let swap_contract_instance = MyContract::new(swap_contract_id.to_string(), wallet.clone());
+
+    (swap_contract_instance, swap_contract_id, token_contract_instance, token_contract_id)
+}
+
+// Pool invariant: the coins it holds cover the reserves plus every user's deposits.
+// check_solvency reverts on a shortfall, and the invariant is checked again against the balances the node reports.
+async fn assert_solvent(instance: &MyContract, pool_id: &ContractId, assets: [ContractId; 2]) {
+    let response = instance.check_solvency().call().await.unwrap();
+    assert!(response.value);
+
+    for asset in assets.iter() {
+        let balance = instance.get_balances(pool_id.clone(), asset.clone()).call().await.unwrap().value;
+        let reserve = instance.test_current_reserve(**asset).call().await.unwrap().value;
+        let deposits = instance.get_total_deposits(asset.clone()).call().await.unwrap().value;
+        assert!(balance >= reserve + deposits);
+    }
+}
+
+// Send a call and check the pool is still solvent afterwards, whether the call went through or not
+async fn call_solvent<D: Detokenize + Debug>(
+    call: ContractCallHandler<D>,
+    instance: &MyContract,
+    pool_id: &ContractId,
+    assets: [ContractId; 2],
+) -> Result<CallResponse<D>, Error> {
+    let result = call.call().await;
+    assert_solvent(instance, pool_id, assets).await;
+
+    result
+}
+
 #[tokio::test]
 async fn can_deposit() {
     let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) = get_contract_instance().await;
@@ -45,6 +105,24 @@ async fn can_deposit() {
     // Now you have an instance of your contract you can use to test each function
 }
 
+#[tokio::test]
+async fn can_log_depositor() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let response = _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(100), None))
+        .call()
+        .await
+        .unwrap();
+
+    // SenderLog carries the 32 bytes of the depositor's key, for a wallet its address
+    let sender: String = wallet.address().iter().map(|b| format!("{:02x}", b)).collect();
+    assert!(response.logs.iter().any(|log| log.contains(&sender)));
+}
+
 #[tokio::test]
 async fn can_get_balance() {
     let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) = get_contract_instance().await;
@@ -168,6 +246,8 @@ async fn can_add_liquidity() {
         .unwrap();
     assert_eq!(coins[0].amount, 50u64.into());
     
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
     // Deposit 50 native assets
     _swap_contract_instance
         .deposit()
@@ -175,6 +255,7 @@ async fn can_add_liquidity() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     // deposit 50 alt tokens into the Exchange contract
     _swap_contract_instance
@@ -186,24 +267,27 @@ async fn can_add_liquidity() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
     
     // Add initial liquidity, setting proportion 1:1
-    // where lp tokens returned should be equal to the eth_amount deposited 50
+    // where lp tokens returned should be equal to the eth_amount deposited 50,
+    // less the 1 LP token locked on the first mint
     _swap_contract_instance
         .add_liquidity(1, 1000)
         .append_variable_outputs(1)
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
-    // Check LP tokens amount to be 50
+    // Check LP tokens amount to be 49
     assert_eq!(
         wallet
-            .get_spendable_coins(&lp_token_id, 50)
+            .get_spendable_coins(&lp_token_id, 49)
             .await
             .unwrap()[0]
             .amount,
-        50u64.into()
+        49u64.into()
     );
 
     // Fund the wallet again with some alt tokens
@@ -221,6 +305,7 @@ async fn can_add_liquidity() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     // Deposit 100 alt tokens
     _swap_contract_instance
@@ -232,6 +317,7 @@ async fn can_add_liquidity() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     // Add liquidity for the second time. Keeping the proportion 1:1
     // It should return the same amount of LP as the amount of ETH deposited
@@ -241,16 +327,17 @@ async fn can_add_liquidity() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
     assert_eq!(result.value, 100);
 
-    // Inspect the wallet for LP tokens - should see 50 LP tokens + 100 LP tokens
+    // Inspect the wallet for LP tokens - should see 49 LP tokens + 100 LP tokens
     let lp_tokens = wallet
-        .get_spendable_coins(&lp_token_id, 150)
+        .get_spendable_coins(&lp_token_id, 149)
         .await
         .unwrap();
     assert!(
-        (lp_tokens[0].amount == 50u64.into()) && (lp_tokens[1].amount == 100u64.into())
-        || (lp_tokens[0].amount == 100u64.into()) && (lp_tokens[1].amount == 50u64.into())
+        (lp_tokens[0].amount == 49u64.into()) && (lp_tokens[1].amount == 100u64.into())
+        || (lp_tokens[0].amount == 100u64.into()) && (lp_tokens[1].amount == 49u64.into())
     );
 }
 
@@ -314,6 +401,8 @@ async fn can_remove_liquidity() {
         .unwrap();
     assert_eq!(coins[0].amount, 50u64.into());
     
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
     // Deposit 50 native assets
     _swap_contract_instance
         .deposit()
@@ -321,6 +410,7 @@ async fn can_remove_liquidity() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     // deposit 50 alt tokens into the Exchange contract
     _swap_contract_instance
@@ -332,24 +422,27 @@ async fn can_remove_liquidity() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
     
     // Add initial liquidity, setting proportion 1:1
-    // where lp tokens returned should be equal to the eth_amount deposited 50
+    // where lp tokens returned should be equal to the eth_amount deposited 50,
+    // less the 1 LP token locked on the first mint
     _swap_contract_instance
         .add_liquidity(1, 1000)
         .append_variable_outputs(1)
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
-    // Check LP tokens amount to be 50
+    // Check LP tokens amount to be 49
     assert_eq!(
         wallet
-            .get_spendable_coins(&lp_token_id, 50)
+            .get_spendable_coins(&lp_token_id, 49)
             .await
             .unwrap()[0]
             .amount,
-        50u64.into()
+        49u64.into()
     );
 
     // Remove 30 native and 30 alt tokens 
@@ -363,19 +456,20 @@ async fn can_remove_liquidity() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
     assert_eq!(result.value.eth_amount, 30);
     assert_eq!(result.value.token_amount, 30);
     
     // Inspect the wallet for LP tokens
-    // It should have 20 lp tokens
+    // It should have 19 lp tokens
     let spendable_coins = wallet
-        .get_spendable_coins(&lp_token_id, 20)
+        .get_spendable_coins(&lp_token_id, 19)
         .await
         .unwrap();
     let total_amount: u64 = spendable_coins.iter().map(|c| c.amount.0).sum();
 
-    // Inspect the wallet for LP tokens to be 20
-    assert_eq!(total_amount, 20);
+    // Inspect the wallet for LP tokens to be 19
+    assert_eq!(total_amount, 19);
 }
 
 #[tokio::test]
@@ -442,6 +536,8 @@ async fn can_swap() {
         .unwrap();
     assert_eq!(coins[0].amount, 500000u64.into());
     
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
     // Deposit 50 native assets
     let log = _swap_contract_instance
         .deposit()
@@ -450,6 +546,7 @@ async fn can_swap() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
     println!("Total amount being deposited to Swap Contract: {:?}\n", log.logs);
 
     let response = _swap_contract_instance
@@ -470,6 +567,7 @@ async fn can_swap() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     let response = _swap_contract_instance
         .get_balance(alt_asset_id)
@@ -479,13 +577,15 @@ async fn can_swap() {
     println!("Alt token balance: {:?}\n", response.value);
     
     // Add initial liquidity, setting proportion 1:1
-    // where lp tokens returned should be equal to the eth_amount deposited 50
+    // where lp tokens returned should be equal to the eth_amount deposited 50,
+    // less the 1 LP token locked on the first mint
     let log = _swap_contract_instance
         .add_liquidity(1, 1000)
         .append_variable_outputs(1)
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     let response = _swap_contract_instance
         .get_balance(native_asset_id)
@@ -507,11 +607,11 @@ async fn can_swap() {
     // Check LP tokens amount to be 50
     assert_eq!(
         wallet
-            .get_spendable_coins(&lp_token_id, 50000)
+            .get_spendable_coins(&lp_token_id, 49999)
             .await
             .unwrap()[0]
             .amount,
-        50000u64.into()
+        49999u64.into()
     );
 
     // Inspect the wallet for alt tokens
@@ -530,15 +630,16 @@ async fn can_swap() {
     */
     
     let result_native = _swap_contract_instance
-        .swap(50, 5)
+        .swap(50, 5, 1000)
         .call_params(CallParameters::new(
-            Some(5000),
+            Some(50),
             Some(native_token_id.clone()),
         ))
         .append_variable_outputs(1)
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
     assert!(result_native.value > 0);
     println!("Result is {}", result_native.value);
     println!("Token Native and Token Alt BEFORE and AFTER the swap: {:?}", result_native.logs);
@@ -551,7 +652,7 @@ async fn can_swap() {
     println!("All swap contract balances: {:?}\n", balances);
 
     let result_alt = _swap_contract_instance
-        .swap(50, 5)
+        .swap(50, 5, 1000)
         .call_params(CallParameters::new(
             Some(50),
             Some(alt_token_id.clone()),
@@ -560,6 +661,7 @@ async fn can_swap() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
     assert!(result_alt.value > 0);
     println!("Result is {}", result_alt.value);
     println!("Token Alt and Token Native BEFORE and AFTER the swap: {:?}", result_alt.logs);
@@ -636,6 +738,8 @@ async fn can_add_liquidity_to_existing_supply() {
         .unwrap();
     assert_eq!(coins[0].amount, 500000u64.into());
     
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
     // Deposit 50 native assets
     _swap_contract_instance
         .deposit()
@@ -644,6 +748,7 @@ async fn can_add_liquidity_to_existing_supply() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     let response = _swap_contract_instance
         .get_balance(native_asset_id)
@@ -663,6 +768,7 @@ async fn can_add_liquidity_to_existing_supply() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     let response = _swap_contract_instance
         .get_balance(alt_asset_id)
@@ -672,13 +778,15 @@ async fn can_add_liquidity_to_existing_supply() {
     println!("Alt token balance: {:?}\n", response.value);
     
     // Add initial liquidity, setting proportion 1:1
-    // where lp tokens returned should be equal to the eth_amount deposited 50
+    // where lp tokens returned should be equal to the eth_amount deposited 50,
+    // less the 1 LP token locked on the first mint
     let log = _swap_contract_instance
         .add_liquidity(1, 1000)
         .append_variable_outputs(1)
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     let response = _swap_contract_instance
         .get_balance(native_asset_id)
@@ -700,11 +808,11 @@ async fn can_add_liquidity_to_existing_supply() {
     // Check LP tokens amount to be 50
     assert_eq!(
         wallet
-            .get_spendable_coins(&lp_token_id, 50000)
+            .get_spendable_coins(&lp_token_id, 49999)
             .await
             .unwrap()[0]
             .amount,
-        50000u64.into()
+        49999u64.into()
     );
     
     // ADDING LIQUIDITY SECOND TIME
@@ -761,6 +869,7 @@ async fn can_add_liquidity_to_existing_supply() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     let response = _swap_contract_instance
         .get_balance(native_asset_id)
@@ -780,6 +889,7 @@ async fn can_add_liquidity_to_existing_supply() {
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     let response = _swap_contract_instance
         .get_balance(alt_asset_id)
@@ -789,13 +899,15 @@ async fn can_add_liquidity_to_existing_supply() {
     println!("Alt token balance: {:?}\n", response.value);
     
     // Add initial liquidity, setting proportion 1:1
-    // where lp tokens returned should be equal to the eth_amount deposited 50
+    // where lp tokens returned should be equal to the eth_amount deposited 50,
+    // less the 1 LP token locked on the first mint
     let log = _swap_contract_instance
         .add_liquidity(1, 1000)
         .append_variable_outputs(1)
         .call()
         .await
         .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
 
     let response = _swap_contract_instance
         .get_balance(native_asset_id)
@@ -826,4 +938,2536 @@ async fn can_add_liquidity_to_existing_supply() {
     );
     */
     */
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn can_add_liquidity_to_imbalanced_pool() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let alt_token_id = AssetId::from(*_token_contract_id.clone());
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // Push the pool off balance
+    _swap_contract_instance
+        .swap(20000, 1, 1000)
+        .call_params(CallParameters::new(Some(20000), Some(AssetId::from(*NATIVE_ASSET_ID))))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    let eth_reserve = _swap_contract_instance.test_current_reserve(*NATIVE_ASSET_ID).call().await.unwrap().value;
+
+    // A tenth of the native reserve, with more alt tokens than the ratio takes
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(eth_reserve / 10), None))
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(eth_reserve / 10), Some(alt_token_id)))
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+
+    // D is compared net of the imbalance fees, so the shares minted follow the deposit
+    // and not just the fees
+    let result = _swap_contract_instance
+        .add_liquidity(1, 1000)
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.value <= 5000);
+    assert!(result.value >= 4990);
+}
+
+#[tokio::test]
+async fn can_transfer_deposit() {
+    // Launch a local network with two wallets
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let _swap_contract_id = Contract::deploy("./out/debug/stable_swap.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+
+    let _swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), wallet.clone());
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    // Native asset id
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+
+    // Deposit 100 native assets
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(100), None))
+        .call()
+        .await
+        .unwrap();
+
+    // Move 40 of them to the other wallet's internal balance
+    _swap_contract_instance
+        .transfer_deposit(Sender::Address(other_wallet.address()), native_asset_id.clone(), 40)
+        .call()
+        .await
+        .unwrap();
+
+    let response = _swap_contract_instance
+        .get_balance(native_asset_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 60);
+
+    let response = _other_swap_contract_instance
+        .get_balance(native_asset_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 40);
+
+    // Transferring more than the remaining deposit must fail
+    let result = _swap_contract_instance
+        .transfer_deposit(Sender::Address(other_wallet.address()), native_asset_id.clone(), 61)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // The receiver can withdraw the transferred balance
+    _other_swap_contract_instance
+        .withdraw(40, native_asset_id.clone())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    let response = _other_swap_contract_instance
+        .get_balance(native_asset_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 0);
+}
+
+#[tokio::test]
+async fn can_transfer_deposit_as_operator() {
+    // Launch a local network with an owner and an operator wallet
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let operator_wallet = wallets[1].clone();
+
+    let _swap_contract_id = Contract::deploy("./out/debug/stable_swap.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+
+    let _swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), wallet.clone());
+    let _operator_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), operator_wallet.clone());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let owner = Sender::Address(wallet.address());
+    let operator = Sender::Address(operator_wallet.address());
+
+    // Deposit 100 native assets
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(100), None))
+        .call()
+        .await
+        .unwrap();
+
+    // Without an approval the operator cannot move anything
+    let result = _operator_swap_contract_instance
+        .transfer_deposit_from(owner.clone(), operator.clone(), native_asset_id.clone(), 10)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Approve the operator for 30 native assets
+    _swap_contract_instance
+        .approve_operator(operator.clone(), native_asset_id.clone(), 30)
+        .call()
+        .await
+        .unwrap();
+
+    let response = _swap_contract_instance
+        .get_allowance(owner.clone(), operator.clone(), native_asset_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 30);
+
+    // Operator moves 20 to itself, spending part of the allowance
+    _operator_swap_contract_instance
+        .transfer_deposit_from(owner.clone(), operator.clone(), native_asset_id.clone(), 20)
+        .call()
+        .await
+        .unwrap();
+
+    let response = _swap_contract_instance
+        .get_allowance(owner.clone(), operator.clone(), native_asset_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 10);
+
+    let response = _swap_contract_instance
+        .get_balance(native_asset_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 80);
+
+    let response = _operator_swap_contract_instance
+        .get_balance(native_asset_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 20);
+
+    // The remaining allowance is 10, so moving 11 must fail
+    let result = _operator_swap_contract_instance
+        .transfer_deposit_from(owner, operator, native_asset_id, 11)
+        .call()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_get_deposit_of_any_account() {
+    // Launch a local network with a depositor and a support wallet
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let support_wallet = wallets[1].clone();
+
+    let _swap_contract_id = Contract::deploy("./out/debug/stable_swap.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+
+    let _swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), wallet.clone());
+    let _support_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), support_wallet.clone());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+
+    // Deposit 25 native assets
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(25), None))
+        .call()
+        .await
+        .unwrap();
+
+    // Another account can look up the depositor's balance
+    let response = _support_swap_contract_instance
+        .get_deposit(Sender::Address(wallet.address()), native_asset_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 25);
+
+    // The ledger slot recomputed off-chain holds the same balance
+    let slot = utils::key_deposits(*wallet.address(), *native_asset_id);
+    let response = _support_swap_contract_instance
+        .test_current_reserve(slot)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 25);
+
+    // Accounts without deposits read as zero
+    let response = _support_swap_contract_instance
+        .get_deposit(Sender::Address(support_wallet.address()), native_asset_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 0);
+}
+
+#[tokio::test]
+async fn can_withdraw_all() {
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) = get_contract_instance().await;
+
+    let wallet = _swap_contract_instance._get_wallet();
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let alt_token_id = AssetId::from(*_token_contract_id.clone());
+
+    // Fund the wallet with some alt tokens
+    _token_contract_instance.mint_coins(1000).call().await.unwrap();
+    _token_contract_instance
+        .transfer_coins_to_output(70, _token_contract_id.clone(), wallet.address())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    // Deposit both assets
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(30), None))
+        .call()
+        .await
+        .unwrap();
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(70), Some(alt_token_id.clone())))
+        .call()
+        .await
+        .unwrap();
+
+    _swap_contract_instance
+        .withdraw_all()
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+
+    // Both ledger slots are empty
+    let response = _swap_contract_instance
+        .get_balance(native_asset_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 0);
+
+    let slot = utils::key_deposits(*wallet.address(), *_token_contract_id);
+    let response = _swap_contract_instance
+        .test_current_reserve(slot)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 0);
+
+    // The alt tokens are back in the wallet
+    let coins = wallet
+        .get_spendable_coins(&alt_token_id, 70)
+        .await
+        .unwrap();
+    let total_amount: u64 = coins.iter().map(|c| c.amount.0).sum();
+    assert_eq!(total_amount, 70);
+}
+
+#[tokio::test]
+async fn can_flash_loan() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let receiver_id = Contract::deploy(
+        "../flash_loan_receiver/out/debug/flash_loan_receiver.bin",
+        &wallet,
+        TxParameters::default(),
+    )
+    .await
+    .unwrap();
+    let receiver_instance = FlashLoanReceiverContract::new(receiver_id.to_string(), wallet.clone());
+    receiver_instance.set_repay(true).call().await.unwrap();
+
+    // Give the receiver enough alt tokens to cover the fee
+    _token_contract_instance
+        .force_transfer_coins(100, _token_contract_id.clone(), receiver_id.clone())
+        .call()
+        .await
+        .unwrap();
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // Borrow 10000 alt tokens, fee is 0.09%
+    _swap_contract_instance
+        .flash_loan(_token_contract_id.clone(), 10000, receiver_id.clone(), [0u8; 32])
+        .set_contracts(&[receiver_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+
+    // The fee is credited to the alt token reserve
+    let response = _swap_contract_instance
+        .test_current_reserve(*_token_contract_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 50000 + 9);
+
+    // The receiver paid back the loan plus the fee
+    let response = _token_contract_instance
+        .get_balance(receiver_id, _token_contract_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 100 - 9);
+}
+
+#[tokio::test]
+async fn cannot_flash_loan_without_repaying() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let receiver_id = Contract::deploy(
+        "../flash_loan_receiver/out/debug/flash_loan_receiver.bin",
+        &wallet,
+        TxParameters::default(),
+    )
+    .await
+    .unwrap();
+    let receiver_instance = FlashLoanReceiverContract::new(receiver_id.to_string(), wallet.clone());
+    receiver_instance.set_repay(false).call().await.unwrap();
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // The receiver keeps the funds, so the whole call reverts
+    let result = _swap_contract_instance
+        .flash_loan(_token_contract_id.clone(), 10000, receiver_id.clone(), [0u8; 32])
+        .set_contracts(&[receiver_id.clone()])
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    // Reserves are untouched
+    let response = _swap_contract_instance
+        .test_current_reserve(*_token_contract_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 50000);
+
+    // Borrowing more than the reserve is rejected too
+    let result = _swap_contract_instance
+        .flash_loan(_token_contract_id.clone(), 50001, receiver_id.clone(), [0u8; 32])
+        .set_contracts(&[receiver_id])
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_flash_swap() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+
+    let receiver_id = Contract::deploy(
+        "../flash_loan_receiver/out/debug/flash_loan_receiver.bin",
+        &wallet,
+        TxParameters::default(),
+    )
+    .await
+    .unwrap();
+    let receiver_instance = FlashLoanReceiverContract::new(receiver_id.to_string(), wallet.clone());
+
+    // Fund the receiver with alt tokens it pays for the native output with
+    _token_contract_instance
+        .force_transfer_coins(2000, _token_contract_id.clone(), receiver_id.clone())
+        .call()
+        .await
+        .unwrap();
+    receiver_instance.set_swap_payment(1100).call().await.unwrap();
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // Take 1000 native assets out, pay 1100 alt tokens back in the callback
+    let result = _swap_contract_instance
+        .flash_swap(native_asset_id.clone(), 1000, receiver_id.clone(), [0u8; 32])
+        .set_contracts(&[receiver_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert_eq!(result.value, 1100);
+
+    let response = _swap_contract_instance
+        .test_current_reserve(*native_asset_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 50000 - 1000);
+
+    let response = _swap_contract_instance
+        .test_current_reserve(*_token_contract_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 50000 + 1100);
+}
+
+#[tokio::test]
+async fn cannot_flash_swap_when_underpaying() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+
+    let receiver_id = Contract::deploy(
+        "../flash_loan_receiver/out/debug/flash_loan_receiver.bin",
+        &wallet,
+        TxParameters::default(),
+    )
+    .await
+    .unwrap();
+    let receiver_instance = FlashLoanReceiverContract::new(receiver_id.to_string(), wallet.clone());
+
+    _token_contract_instance
+        .force_transfer_coins(2000, _token_contract_id.clone(), receiver_id.clone())
+        .call()
+        .await
+        .unwrap();
+
+    // 900 alt tokens for 1000 native assets breaks the invariant
+    receiver_instance.set_swap_payment(900).call().await.unwrap();
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+    let result = _swap_contract_instance
+        .flash_swap(native_asset_id.clone(), 1000, receiver_id.clone(), [0u8; 32])
+        .set_contracts(&[receiver_id])
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    let response = _swap_contract_instance
+        .test_current_reserve(*native_asset_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 50000);
+}
+
+#[tokio::test]
+async fn can_get_twap() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    let start = _swap_contract_instance
+        .get_cumulative_prices()
+        .call()
+        .await
+        .unwrap()
+        .value;
+
+    // A balanced pool trades at 1:1
+    produce_blocks(&wallet, 5).await;
+    let twap = _swap_contract_instance.get_twap(3).call().await.unwrap().value;
+    assert_eq!(twap.token_price, 1_000_000);
+    assert_eq!(twap.eth_price, 1_000_000);
+
+    // Push native assets into the pool, making the alt token more expensive
+    _swap_contract_instance
+        .swap(5000, 1, 1000)
+        .call_params(CallParameters::new(Some(5000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    produce_blocks(&wallet, 5).await;
+
+    let current = _swap_contract_instance
+        .get_cumulative_prices()
+        .call()
+        .await
+        .unwrap()
+        .value;
+    assert!(current.token_price_cumulative > start.token_price_cumulative);
+
+    // Short window only covers blocks after the swap
+    let twap_after_swap = _swap_contract_instance.get_twap(1).call().await.unwrap().value;
+    assert!(twap_after_swap.token_price > 1_000_000);
+    assert!(twap_after_swap.eth_price < 1_000_000);
+
+    // Long window averages the balanced blocks with the ones after the swap
+    let window = current.height - start.height + 1;
+    let twap_long = _swap_contract_instance.get_twap(window).call().await.unwrap().value;
+    assert!(twap_long.token_price > 1_000_000);
+    assert!(twap_long.token_price < twap_after_swap.token_price);
+    assert!(twap_long.eth_price < 1_000_000);
+    assert!(twap_long.eth_price > twap_after_swap.eth_price);
+}
+
+#[tokio::test]
+async fn cannot_get_twap_beyond_observations() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    // No observation is older than the pool itself
+    let result = _swap_contract_instance.get_twap(1000).call().await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn ema_price_oracle_lags_large_swap() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    // Use a short half-time of 10 blocks
+    _swap_contract_instance.set_ma_exp_time(10).call().await.unwrap();
+
+    // Balanced pool, spot and EMA agree
+    let spot = _swap_contract_instance.get_p().call().await.unwrap().value;
+    let oracle = _swap_contract_instance.price_oracle().call().await.unwrap().value;
+    assert_eq!(spot, 1_000_000);
+    assert_eq!(oracle, spot);
+
+    // A large swap moves the spot price at once
+    _swap_contract_instance
+        .swap(20000, 1, 1000)
+        .call_params(CallParameters::new(Some(20000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    let spot = _swap_contract_instance.get_p().call().await.unwrap().value;
+    assert!(spot > 1_000_000);
+
+    // The EMA only moved a little towards it
+    let oracle = _swap_contract_instance.price_oracle().call().await.unwrap().value;
+    assert!(oracle >= 1_000_000);
+    assert!(oracle < spot);
+    assert!(spot - oracle > (spot - 1_000_000) / 2);
+
+    // After six half-times it has caught up most of the way
+    produce_blocks(&wallet, 60).await;
+    let oracle = _swap_contract_instance.price_oracle().call().await.unwrap().value;
+    assert!(oracle <= spot);
+    assert!(spot - oracle < (spot - 1_000_000) / 10);
+}
+
+#[tokio::test]
+async fn only_owner_can_set_ma_exp_time() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let _swap_contract_id = Contract::deploy("./out/debug/stable_swap.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let _swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), wallet.clone());
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    // Nobody can configure an uninitialized pool
+    let result = _swap_contract_instance.set_ma_exp_time(10).call().await;
+    assert!(result.is_err());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let token_id = ContractId::new([1u8; 32]);
+
+    // Only the deployer can initialize the pool
+    let result = _other_swap_contract_instance
+        .initialize(Sender::Address(other_wallet.address()), [native_asset_id.clone(), token_id.clone()], 1000, 300, 1000)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    _swap_contract_instance
+        .initialize(Sender::Address(wallet.address()), [native_asset_id.clone(), token_id.clone()], 1000, 300, 1000)
+        .call()
+        .await
+        .unwrap();
+    let response = _other_swap_contract_instance.get_owner().call().await.unwrap();
+    assert_eq!(response.value, *wallet.address());
+
+    // The pool can only be initialized once
+    let result = _other_swap_contract_instance
+        .initialize(Sender::Address(other_wallet.address()), [native_asset_id, token_id], 1000, 300, 1000)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    let result = _other_swap_contract_instance.set_ma_exp_time(10).call().await;
+    assert!(result.is_err());
+
+    _swap_contract_instance.set_ma_exp_time(10).call().await.unwrap();
+}
+
+#[tokio::test]
+async fn can_get_spot_price_and_price_impact() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    // Balanced pool trades at 1:1 in both directions
+    let eth_to_token = _swap_contract_instance.spot_price(0, 1).call().await.unwrap().value;
+    let token_to_eth = _swap_contract_instance.spot_price(1, 0).call().await.unwrap().value;
+    assert_eq!(eth_to_token, 1_000_000);
+    assert_eq!(token_to_eth, 1_000_000);
+
+    // Bigger trades have a bigger price impact
+    let small_impact = _swap_contract_instance.price_impact(0, 1, 1000).call().await.unwrap().value;
+    let large_impact = _swap_contract_instance.price_impact(0, 1, 20000).call().await.unwrap().value;
+    assert!(small_impact < large_impact);
+
+    // Same index twice is not a pair
+    let result = _swap_contract_instance.spot_price(0, 0).call().await;
+    assert!(result.is_err());
+
+    // Sell native assets into the pool
+    _swap_contract_instance
+        .swap(10000, 1, 1000)
+        .call_params(CallParameters::new(Some(10000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    // Native assets are now worth less alt tokens, and alt tokens more native assets
+    let eth_to_token = _swap_contract_instance.spot_price(0, 1).call().await.unwrap().value;
+    let token_to_eth = _swap_contract_instance.spot_price(1, 0).call().await.unwrap().value;
+    assert!(eth_to_token < 1_000_000);
+    assert!(token_to_eth > 1_000_000);
+}
+
+#[tokio::test]
+async fn cannot_initialize_with_invalid_config() {
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) = get_contract_instance().await;
+
+    let wallet = _swap_contract_instance._get_wallet();
+    let owner = Sender::Address(wallet.address());
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+
+    // Same asset on both sides
+    let result = _swap_contract_instance
+        .initialize(owner.clone(), [native_asset_id.clone(), native_asset_id.clone()], 1000, 300, 1000)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Fee of 100%
+    let result = _swap_contract_instance
+        .initialize(owner.clone(), [native_asset_id.clone(), _token_contract_id.clone()], 1000, 1_000_000, 1000)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // No amplification
+    let result = _swap_contract_instance
+        .initialize(owner.clone(), [native_asset_id.clone(), _token_contract_id.clone()], 0, 300, 1000)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Nothing locked on the first mint
+    let result = _swap_contract_instance
+        .initialize(owner.clone(), [native_asset_id.clone(), _token_contract_id.clone()], 1000, 300, 0)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Deposits into the default pair hold the pair in place until they are withdrawn
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*NATIVE_ASSET_ID))))
+        .call()
+        .await
+        .unwrap();
+    let result = _swap_contract_instance
+        .initialize(owner.clone(), [native_asset_id.clone(), _token_contract_id.clone()], 1000, 300, 1000)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    _swap_contract_instance
+        .withdraw(1000, native_asset_id.clone())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    _swap_contract_instance
+        .initialize(owner, [native_asset_id, _token_contract_id], 1000, 300, 1000)
+        .call()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn can_quote_swaps() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    let response = _swap_contract_instance.get_assets().call().await.unwrap();
+    assert_eq!(response.value, [native_asset_id.clone(), _token_contract_id.clone()]);
+
+    // Quoting the input for a quote's output gets back to about the same input
+    let dy = _swap_contract_instance
+        .get_dy(native_asset_id.clone(), 1000)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    let dx = _swap_contract_instance
+        .get_dx(_token_contract_id.clone(), dy)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    assert!(dx >= 1000);
+    assert!(dx <= 1005);
+
+    // The swap pays out the quoted amount to the caller
+    let result = _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(result.value, dy);
+}
+
+#[tokio::test]
+async fn can_add_liquidity_to_a_pool_of_two_tokens() {
+    let wallet = launch_provider_and_get_wallet().await;
+
+    let swap_contract_id = Contract::deploy("./out/debug/stable_swap.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let _swap_contract_instance = MyContract::new(swap_contract_id.to_string(), wallet.clone());
+
+    // Neither side is the base asset, the salts keep the two token contract ids apart
+    let mut token_ids = vec![];
+    for salt in [1u8, 2] {
+        token_ids.push(get_funded_token(&[wallet.clone()], salt, 50000).await);
+    }
+
+    _swap_contract_instance
+        .initialize(Sender::Address(wallet.address()), [token_ids[0].clone(), token_ids[1].clone()], 1000, 300, 1000)
+        .call()
+        .await
+        .unwrap();
+
+    let assets = [token_ids[0].clone(), token_ids[1].clone()];
+    for token_id in token_ids.iter() {
+        _swap_contract_instance
+            .deposit()
+            .call_params(CallParameters::new(Some(50000), Some(AssetId::from(**token_id))))
+            .call()
+            .await
+            .unwrap();
+        assert_solvent(&_swap_contract_instance, &swap_contract_id, assets.clone()).await;
+    }
+
+    // add_liquidity forwards no coins, so the call's asset id is the base asset, which this pool doesn't hold
+    let result = _swap_contract_instance
+        .add_liquidity(1, 1000)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &swap_contract_id, assets.clone()).await;
+    assert!(result.value > 0);
+    for token_id in token_ids.iter() {
+        let response = _swap_contract_instance.test_current_reserve(**token_id).call().await.unwrap();
+        assert_eq!(response.value, 50000);
+    }
+}
+
+// Pool of a new token against the LP token of a base pool, returns the metapool with its token
+async fn get_metapool(
+    wallet: &LocalWallet,
+    base_pool_id: &ContractId,
+    meta_liquidity: u64,
+    lp_liquidity: u64,
+) -> (MyContract, ContractId, ContractId) {
+    let meta_token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", wallet, 1).await;
+    let meta_token_instance = TestToken::new(meta_token_id.to_string(), wallet.clone());
+    meta_token_instance.mint_coins(meta_liquidity * 10).call().await.unwrap();
+    meta_token_instance
+        .transfer_coins_to_output(meta_liquidity * 5, meta_token_id.clone(), wallet.address())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    let metapool_id = deploy_with_salt("./out/debug/stable_swap.bin", wallet, 1).await;
+    let metapool_instance = MyContract::new(metapool_id.to_string(), wallet.clone());
+
+    metapool_instance
+        .initialize(
+            Sender::Address(wallet.address()),
+            [meta_token_id.clone(), base_pool_id.clone()],
+            1000,
+            300,
+            1000,
+        )
+        .call()
+        .await
+        .unwrap();
+    metapool_instance
+        .set_base_pool(base_pool_id.clone())
+        .call()
+        .await
+        .unwrap();
+
+    metapool_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(meta_liquidity), Some(AssetId::from(*meta_token_id))))
+        .call()
+        .await
+        .unwrap();
+    metapool_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(lp_liquidity), Some(AssetId::from(**base_pool_id))))
+        .call()
+        .await
+        .unwrap();
+    metapool_instance
+        .add_liquidity(1, 1000)
+        .set_contracts(&[base_pool_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    (metapool_instance, metapool_id, meta_token_id)
+}
+
+#[tokio::test]
+async fn can_swap_on_metapool() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 100000).await;
+
+    // The first LP got one LP token per native asset for both sides, so each LP token is worth 2
+    let response = _swap_contract_instance.get_virtual_price().call().await.unwrap();
+    assert_eq!(response.value, 2_000_000);
+
+    // Balanced in value: 50000 meta tokens against 25000 LP tokens
+    let (metapool_instance, _metapool_id, meta_token_id) =
+        get_metapool(&wallet, &_swap_contract_id, 50000, 25000).await;
+
+    let response = metapool_instance
+        .spot_price(0, 1)
+        .set_contracts(&[_swap_contract_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    assert!(response.value >= 499_000 && response.value <= 501_000);
+
+    let meta_assets = [meta_token_id.clone(), _swap_contract_id.clone()];
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // 1000 meta tokens buy about 500 LP tokens
+    let result = metapool_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*meta_token_id))))
+        .set_contracts(&[_swap_contract_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&metapool_instance, &_metapool_id, meta_assets.clone()).await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.value >= 495);
+    assert!(result.value <= 500);
+}
+
+#[tokio::test]
+async fn can_exchange_underlying_on_metapool() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 100000).await;
+    let (metapool_instance, _metapool_id, meta_token_id) =
+        get_metapool(&wallet, &_swap_contract_id, 50000, 25000).await;
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+
+    let meta_assets = [meta_token_id.clone(), _swap_contract_id.clone()];
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // Meta token in, native asset of the base pool out
+    let result = metapool_instance
+        .exchange_underlying(native_asset_id.clone(), 990, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*meta_token_id))))
+        .set_contracts(&[_swap_contract_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&metapool_instance, &_metapool_id, meta_assets.clone()).await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.value >= 990);
+    assert!(result.value < 1000);
+
+    // Alt token of the base pool in, meta token out
+    let result = metapool_instance
+        .exchange_underlying(meta_token_id.clone(), 990, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*_token_contract_id))))
+        .set_contracts(&[_swap_contract_id.clone()])
+        .append_variable_outputs(3)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&metapool_instance, &_metapool_id, meta_assets.clone()).await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.value >= 990);
+    assert!(result.value < 1000);
+
+    // The route fails as a whole when it can't pay the minimum, or once the deadline has passed
+    let result = metapool_instance
+        .exchange_underlying(native_asset_id.clone(), 1000, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*meta_token_id))))
+        .set_contracts(&[_swap_contract_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&metapool_instance, &_metapool_id, meta_assets.clone()).await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+    let result = metapool_instance
+        .exchange_underlying(native_asset_id.clone(), 990, 1)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*meta_token_id))))
+        .set_contracts(&[_swap_contract_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&metapool_instance, &_metapool_id, meta_assets.clone()).await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    // Underlying in and underlying out is not a metapool trade
+    let result = metapool_instance
+        .exchange_underlying(native_asset_id, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*_token_contract_id))))
+        .set_contracts(&[_swap_contract_id.clone()])
+        .append_variable_outputs(3)
+        .call()
+        .await;
+    assert_solvent(&metapool_instance, &_metapool_id, meta_assets.clone()).await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    // Plain pools have no base pool to exchange through
+    let result = _swap_contract_instance
+        .exchange_underlying(_token_contract_id.clone(), 1, 1000)
+        .call_params(CallParameters::new(Some(1000), None))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_pause_and_unpause() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+    let alt_token_id = AssetId::from(*_token_contract_id);
+    let lp_token_id = AssetId::from(*_swap_contract_id);
+
+    // Deposits made before the pause
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(100), Some(native_token_id)))
+        .call()
+        .await
+        .unwrap();
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(100), Some(alt_token_id)))
+        .call()
+        .await
+        .unwrap();
+
+    _swap_contract_instance.pause().call().await.unwrap();
+    let response = _swap_contract_instance.is_paused().call().await.unwrap();
+    assert_eq!(response.value, true);
+
+    let result = _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    let result = _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(100), Some(native_token_id)))
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    let result = _swap_contract_instance
+        .add_liquidity(1, 1000)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Funds can always leave
+    _swap_contract_instance
+        .withdraw(100, ContractId::new(*NATIVE_ASSET_ID))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    let result = _swap_contract_instance
+        .remove_liquidity(1, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(lp_token_id)))
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(result.value.eth_amount, 1000);
+    assert_eq!(result.value.token_amount, 1000);
+
+    // Trading resumes once unpaused
+    _swap_contract_instance.unpause().call().await.unwrap();
+    _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn cannot_pause_after_kill_deadline() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    // Only the owner can pause, unpause or move the deadline
+    let result = _other_swap_contract_instance.pause().call().await;
+    assert!(result.is_err());
+    let result = _other_swap_contract_instance.unpause().call().await;
+    assert!(result.is_err());
+
+    let kill_deadline = _swap_contract_instance.get_kill_deadline().call().await.unwrap().value;
+    let current_height = _swap_contract_instance.get_cumulative_prices().call().await.unwrap().value.height;
+    assert!(kill_deadline > current_height);
+
+    // The deadline can only be brought forward
+    let result = _swap_contract_instance.set_kill_deadline(kill_deadline + 1).call().await;
+    assert!(result.is_err());
+    let result = _other_swap_contract_instance.set_kill_deadline(current_height + 3).call().await;
+    assert!(result.is_err());
+
+    _swap_contract_instance.set_kill_deadline(current_height + 3).call().await.unwrap();
+    produce_blocks(&wallet, 3).await;
+
+    let result = _swap_contract_instance.pause().call().await;
+    assert!(result.is_err());
+    let response = _swap_contract_instance.is_paused().call().await.unwrap();
+    assert_eq!(response.value, false);
+}
+
+#[tokio::test]
+async fn cannot_reenter_from_flash_loan() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let attacker_id = Contract::deploy(
+        "../reentrant_receiver/out/debug/reentrant_receiver.bin",
+        &wallet,
+        TxParameters::default(),
+    )
+    .await
+    .unwrap();
+
+    // Give the attacker enough alt tokens to cover the fee
+    _token_contract_instance
+        .force_transfer_coins(100, _token_contract_id.clone(), attacker_id.clone())
+        .call()
+        .await
+        .unwrap();
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // The attacker deposits the loan back into the pool from inside the callback
+    let result = _swap_contract_instance
+        .flash_loan(_token_contract_id.clone(), 10000, attacker_id.clone(), [0u8; 32])
+        .set_contracts(&[attacker_id.clone()])
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    // Nothing was credited and the reserves are untouched
+    let response = _swap_contract_instance
+        .get_deposit(Sender::ContractId(attacker_id.clone()), _token_contract_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 0);
+    let response = _swap_contract_instance
+        .test_current_reserve(*_token_contract_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 50000);
+
+    // The guard is released after every call, so the pool keeps working
+    _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(AssetId::from(*NATIVE_ASSET_ID))))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+}
+
+#[tokio::test]
+async fn cannot_reenter_from_flash_swap() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+
+    let attacker_id = Contract::deploy(
+        "../reentrant_receiver/out/debug/reentrant_receiver.bin",
+        &wallet,
+        TxParameters::default(),
+    )
+    .await
+    .unwrap();
+    let attacker_instance = ReentrantReceiverContract::new(attacker_id.to_string(), wallet.clone());
+
+    // The attacker pays for both flash swaps from its own alt tokens, well over the fee
+    _token_contract_instance
+        .force_transfer_coins(2200, _token_contract_id.clone(), attacker_id.clone())
+        .call()
+        .await
+        .unwrap();
+    attacker_instance.set_swap_payment(1100).call().await.unwrap();
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // Paid in full without calling back in, the flash swap goes through
+    _swap_contract_instance
+        .flash_swap(native_asset_id.clone(), 1000, attacker_id.clone(), [0u8; 32])
+        .set_contracts(&[attacker_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    let response = _swap_contract_instance
+        .test_current_reserve(*native_asset_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 49000);
+
+    // The same payment followed by a swap back on the pool can only fail on the guard
+    attacker_instance.set_reenter(true).call().await.unwrap();
+    let result = _swap_contract_instance
+        .flash_swap(native_asset_id.clone(), 1000, attacker_id.clone(), [0u8; 32])
+        .set_contracts(&[attacker_id.clone()])
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    let response = _swap_contract_instance
+        .test_current_reserve(*native_asset_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 49000);
+}
+
+#[tokio::test]
+async fn cannot_inflate_share_price_with_donations() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let attacker = wallets[0].clone();
+    let victim = wallets[1].clone();
+
+    // A pool of two alt tokens, both wallets hold some of each
+    let mut assets = vec![];
+    for salt in 1..3u8 {
+        let token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &attacker, salt).await;
+        let token_instance = TestToken::new(token_id.to_string(), attacker.clone());
+        token_instance.mint_coins(1_000_000).call().await.unwrap();
+        for wallet in wallets.iter() {
+            token_instance
+                .transfer_coins_to_output(100_000, token_id.clone(), wallet.address())
+                .append_variable_outputs(1)
+                .call()
+                .await
+                .unwrap();
+        }
+        assets.push(token_id);
+    }
+    let token_a = assets[0].clone();
+    let token_b = assets[1].clone();
+
+    let pool_id = Contract::deploy("./out/debug/stable_swap.bin", &attacker, TxParameters::default())
+        .await
+        .unwrap();
+    let attacker_pool = MyContract::new(pool_id.to_string(), attacker.clone());
+    let victim_pool = MyContract::new(pool_id.to_string(), victim.clone());
+    let lp_token_id = AssetId::from(*pool_id);
+
+    attacker_pool
+        .initialize(Sender::Address(attacker.address()), [token_a.clone(), token_b.clone()], 1000, 300, 1000)
+        .call()
+        .await
+        .unwrap();
+    let response = attacker_pool.get_minimum_liquidity().call().await.unwrap();
+    assert_eq!(response.value, 1000);
+
+    let pool_assets = [token_a.clone(), token_b.clone()];
+
+    // The attacker opens the pool with the smallest deposit it accepts and is left with a single share
+    for token in [&token_a, &token_b] {
+        attacker_pool
+            .deposit()
+            .call_params(CallParameters::new(Some(1000), Some(AssetId::from(**token))))
+            .call()
+            .await
+            .unwrap();
+        assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    }
+    let result = attacker_pool.add_liquidity(1, 1000).append_variable_outputs(2).call().await;
+    assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    assert!(result.is_err());
+    for token in [&token_a, &token_b] {
+        attacker_pool
+            .deposit()
+            .call_params(CallParameters::new(Some(1), Some(AssetId::from(**token))))
+            .call()
+            .await
+            .unwrap();
+        assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    }
+    let response = attacker_pool.add_liquidity(1, 1000).append_variable_outputs(1).call().await.unwrap();
+    assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    assert_eq!(response.value, 1);
+
+    // Then donates to the reserves by overpaying flash swaps on both sides
+    let receiver_id = Contract::deploy(
+        "../flash_loan_receiver/out/debug/flash_loan_receiver.bin",
+        &attacker,
+        TxParameters::default(),
+    )
+    .await
+    .unwrap();
+    let receiver_instance = FlashLoanReceiverContract::new(receiver_id.to_string(), attacker.clone());
+    for token in [&token_a, &token_b] {
+        TestToken::new(token.to_string(), attacker.clone())
+            .force_transfer_coins(50_000, token.clone(), receiver_id.clone())
+            .call()
+            .await
+            .unwrap();
+    }
+    receiver_instance.set_swap_payment(50_000).call().await.unwrap();
+    for token in [&token_a, &token_b] {
+        attacker_pool
+            .flash_swap(token.clone(), 1, receiver_id.clone(), [0u8; 32])
+            .set_contracts(&[receiver_id.clone()])
+            .call()
+            .await
+            .unwrap();
+        assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    }
+    let response = attacker_pool.test_current_reserve(*token_a).call().await.unwrap();
+    assert_eq!(response.value, 51_000);
+
+    // The victim still gets shares worth their deposit. Without the locked shares the attacker
+    // would own the whole supply and the victim's mint would round down to nothing.
+    for token in [&token_a, &token_b] {
+        victim_pool
+            .deposit()
+            .call_params(CallParameters::new(Some(10_000), Some(AssetId::from(**token))))
+            .call()
+            .await
+            .unwrap();
+        assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    }
+    let victim_lp = victim_pool
+        .add_liquidity(1, 1000)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    assert!(victim_lp > 0);
+
+    let result = victim_pool
+        .remove_liquidity(1, 1, 1000)
+        .call_params(CallParameters::new(Some(victim_lp), Some(lp_token_id)))
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    assert!(result.value.eth_amount >= 9_900);
+    assert!(result.value.token_amount >= 9_900);
+
+    // The donation went to the locked shares, the attacker can't get it back
+    let result = attacker_pool
+        .remove_liquidity(1, 1, 1000)
+        .call_params(CallParameters::new(Some(1), Some(lp_token_id)))
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&attacker_pool, &pool_id, pool_assets.clone()).await;
+    assert!(result.value.eth_amount < 1_000);
+    assert!(result.value.token_amount < 1_000);
+}
+
+#[tokio::test]
+async fn can_sync_and_skim() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+    let assets = [native_asset_id.clone(), _token_contract_id.clone()];
+
+    // A user deposit, which neither skim nor sync may touch
+    call_solvent(
+        _swap_contract_instance
+            .deposit()
+            .call_params(CallParameters::new(Some(100), Some(native_token_id))),
+        &_swap_contract_instance,
+        &_swap_contract_id,
+        assets.clone(),
+    )
+    .await
+    .unwrap();
+
+    // A swap takes exactly `dx`, paying less or more is refused
+    for amount in [500, 1500] {
+        let result = call_solvent(
+            _swap_contract_instance
+                .swap(1000, 1, 1000)
+                .call_params(CallParameters::new(Some(amount), Some(native_token_id)))
+                .append_variable_outputs(1),
+            &_swap_contract_instance,
+            &_swap_contract_id,
+            assets.clone(),
+        )
+        .await;
+        assert!(result.is_err());
+    }
+    call_solvent(
+        _swap_contract_instance
+            .swap(1000, 1, 1000)
+            .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+            .append_variable_outputs(1),
+        &_swap_contract_instance,
+        &_swap_contract_id,
+        assets.clone(),
+    )
+    .await
+    .unwrap();
+
+    // Stray alt tokens sent straight to the pool
+    call_solvent(
+        _token_contract_instance.force_transfer_coins(700, _token_contract_id.clone(), _swap_contract_id.clone()),
+        &_swap_contract_instance,
+        &_swap_contract_id,
+        assets.clone(),
+    )
+    .await
+    .unwrap();
+
+    let native_reserve = _swap_contract_instance.test_current_reserve(*native_asset_id).call().await.unwrap().value;
+    let token_reserve = _swap_contract_instance.test_current_reserve(*_token_contract_id).call().await.unwrap().value;
+
+    // Only the owner can skim the excess, to any recipient
+    let result = call_solvent(
+        _other_swap_contract_instance
+            .skim(Sender::Address(other_wallet.address()))
+            .append_variable_outputs(2),
+        &_swap_contract_instance,
+        &_swap_contract_id,
+        assets.clone(),
+    )
+    .await;
+    assert!(result.is_err());
+    call_solvent(
+        _swap_contract_instance
+            .skim(Sender::Address(other_wallet.address()))
+            .append_variable_outputs(2),
+        &_swap_contract_instance,
+        &_swap_contract_id,
+        assets.clone(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(get_wallet_balance(&other_wallet, &_token_contract_id).await, 700);
+
+    let response = _swap_contract_instance.test_current_reserve(*native_asset_id).call().await.unwrap();
+    assert_eq!(response.value, native_reserve);
+    let response = _swap_contract_instance
+        .get_balances(_swap_contract_id.clone(), native_asset_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, native_reserve + 100);
+    let response = _swap_contract_instance
+        .get_balances(_swap_contract_id.clone(), _token_contract_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, token_reserve);
+
+    // Sync instead folds a donation into the reserves, and only the owner may do it
+    call_solvent(
+        _token_contract_instance.force_transfer_coins(300, _token_contract_id.clone(), _swap_contract_id.clone()),
+        &_swap_contract_instance,
+        &_swap_contract_id,
+        assets.clone(),
+    )
+    .await
+    .unwrap();
+    let result = call_solvent(
+        _other_swap_contract_instance.sync(),
+        &_swap_contract_instance,
+        &_swap_contract_id,
+        assets.clone(),
+    )
+    .await;
+    assert!(result.is_err());
+    call_solvent(_swap_contract_instance.sync(), &_swap_contract_instance, &_swap_contract_id, assets.clone())
+        .await
+        .unwrap();
+
+    let response = _swap_contract_instance.test_current_reserve(*_token_contract_id).call().await.unwrap();
+    assert_eq!(response.value, token_reserve + 300);
+    let response = _swap_contract_instance.test_current_reserve(*native_asset_id).call().await.unwrap();
+    assert_eq!(response.value, native_reserve);
+
+    // The deposit is still there to withdraw
+    call_solvent(
+        _swap_contract_instance
+            .withdraw(100, native_asset_id)
+            .append_variable_outputs(1),
+        &_swap_contract_instance,
+        &_swap_contract_id,
+        assets,
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn can_recover_stray_tokens() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    // A token the pool doesn't trade, sent to it by mistake
+    let stray_token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallet, 1).await;
+    let stray_token_instance = TestToken::new(stray_token_id.to_string(), wallet.clone());
+    stray_token_instance.mint_coins(1000).call().await.unwrap();
+    stray_token_instance
+        .force_transfer_coins(400, stray_token_id.clone(), _swap_contract_id.clone())
+        .call()
+        .await
+        .unwrap();
+
+    // Only the owner can recover it
+    let result = _other_swap_contract_instance
+        .recover_token(stray_token_id.clone(), Sender::Address(other_wallet.address()))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Neither pool asset nor the LP token can be taken this way
+    for asset in [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone(), _swap_contract_id.clone()] {
+        let result = _swap_contract_instance
+            .recover_token(asset, Sender::Address(wallet.address()))
+            .append_variable_outputs(1)
+            .call()
+            .await;
+        assert!(result.is_err());
+    }
+
+    // The owner sends it back to whoever it belongs to
+    let response = _swap_contract_instance
+        .recover_token(stray_token_id.clone(), Sender::Address(other_wallet.address()))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 400);
+
+    let coins = other_wallet
+        .get_spendable_coins(&AssetId::from(*stray_token_id), 400)
+        .await
+        .unwrap();
+    let total_amount: u64 = coins.iter().map(|c| c.amount.0).sum();
+    assert_eq!(total_amount, 400);
+
+    let response = _swap_contract_instance
+        .get_balances(_swap_contract_id.clone(), stray_token_id)
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, 0);
+}
+
+#[tokio::test]
+async fn can_charge_dynamic_fee_off_peg() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    // Without a multiplier the fee is flat
+    let response = _swap_contract_instance.dynamic_fee(0, 1).call().await.unwrap();
+    assert_eq!(response.value, 300);
+
+    // Only the owner sets the multiplier, and not past a 50% fee
+    let result = _other_swap_contract_instance.set_offpeg_fee_multiplier(50_000_000).call().await;
+    assert!(result.is_err());
+    let result = _swap_contract_instance.set_offpeg_fee_multiplier(2_000_000_000).call().await;
+    assert!(result.is_err());
+
+    let flat_balanced = _swap_contract_instance.get_dy(native_asset_id.clone(), 10000).call().await.unwrap().value;
+    _swap_contract_instance.set_offpeg_fee_multiplier(50_000_000).call().await.unwrap();
+    let response = _swap_contract_instance.get_offpeg_fee_multiplier().call().await.unwrap();
+    assert_eq!(response.value, 50_000_000);
+
+    // At the peg the fee stays the base fee
+    let response = _swap_contract_instance.dynamic_fee(0, 1).call().await.unwrap();
+    assert_eq!(response.value, 300);
+    let dynamic_balanced = _swap_contract_instance.get_dy(native_asset_id.clone(), 10000).call().await.unwrap().value;
+    assert!(dynamic_balanced <= flat_balanced);
+    assert!(flat_balanced - dynamic_balanced <= 1);
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // Push the pool off peg
+    _swap_contract_instance
+        .swap(40000, 1, 1000)
+        .call_params(CallParameters::new(Some(40000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+
+    // The fee rises the same way in both directions
+    let fee_eth_to_token = _swap_contract_instance.dynamic_fee(0, 1).call().await.unwrap().value;
+    let fee_token_to_eth = _swap_contract_instance.dynamic_fee(1, 0).call().await.unwrap().value;
+    assert!(fee_eth_to_token > 300);
+    assert_eq!(fee_eth_to_token, fee_token_to_eth);
+
+    // Trading further off peg now costs noticeably more than the flat fee
+    let dynamic_skewed = _swap_contract_instance.get_dy(native_asset_id.clone(), 5000).call().await.unwrap().value;
+    _swap_contract_instance.set_offpeg_fee_multiplier(0).call().await.unwrap();
+    let flat_skewed = _swap_contract_instance.get_dy(native_asset_id.clone(), 5000).call().await.unwrap().value;
+    assert!(flat_skewed - dynamic_skewed >= 2);
+
+    // The swap pays out what was quoted
+    _swap_contract_instance.set_offpeg_fee_multiplier(50_000_000).call().await.unwrap();
+    let result = _swap_contract_instance
+        .swap(5000, 1, 1000)
+        .call_params(CallParameters::new(Some(5000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert_eq!(result.value, dynamic_skewed);
+}
+
+#[tokio::test]
+async fn can_add_liquidity_at_ratio_off_peg() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+    let alt_token_id = AssetId::from(*_token_contract_id.clone());
+
+    _swap_contract_instance.set_offpeg_fee_multiplier(50_000_000).call().await.unwrap();
+
+    // A tenth of the native reserve, add_liquidity takes the alt tokens at the pool's ratio
+    // and refunds the rest
+    let mut minted = vec![];
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+    for skew in [0u64, 40000] {
+        if skew > 0 {
+            _swap_contract_instance
+                .swap(skew, 1, 1000)
+                .call_params(CallParameters::new(Some(skew), Some(native_token_id)))
+                .append_variable_outputs(1)
+                .call()
+                .await
+                .unwrap();
+            assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+        }
+        let eth_reserve = _swap_contract_instance.test_current_reserve(*NATIVE_ASSET_ID).call().await.unwrap().value;
+        _swap_contract_instance
+            .deposit()
+            .call_params(CallParameters::new(Some(eth_reserve / 10), None))
+            .call()
+            .await
+            .unwrap();
+        assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+        _swap_contract_instance
+            .deposit()
+            .call_params(CallParameters::new(Some(eth_reserve / 10), Some(alt_token_id)))
+            .call()
+            .await
+            .unwrap();
+        assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+        let result = _swap_contract_instance
+            .add_liquidity(1, 1000)
+            .append_variable_outputs(2)
+            .call()
+            .await
+            .unwrap();
+        assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+        minted.push(result.value);
+    }
+
+    // The imbalance fee is charged on the distance from the pool's ratio, not from the peg, so
+    // a deposit at the ratio of a skewed pool mints a tenth of the supply just like at the peg
+    assert!(minted[0] <= 5000);
+    assert!(minted[0] >= 4990);
+    assert!(minted[1] <= 5500);
+    assert!(minted[1] >= 5490);
+}
+
+#[tokio::test]
+async fn can_quote_imbalance_fees() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    _swap_contract_instance.set_offpeg_fee_multiplier(10_000_000).call().await.unwrap();
+
+    // A balanced deposit on a balanced pool pays nothing
+    let response = _swap_contract_instance.get_imbalance_fees([50000, 50000]).call().await.unwrap();
+    assert_eq!(response.value, [0, 0]);
+
+    // All on one side: each side pays Curve's half of the swap fee on its distance from the
+    // balance it would have at the new D, raised by how far its average is from D per coin
+    let response = _swap_contract_instance.get_imbalance_fees([500_000, 0]).call().await.unwrap();
+    let expected = utils::imbalance_fees([50000, 50000], [550_000, 50000], 1000, 300, 10_000_000);
+    assert_eq!(response.value, expected);
+    assert_eq!(response.value, [39, 51]);
+}
+
+#[tokio::test]
+async fn can_swap_with_rate_provider() {
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) = get_contract_instance().await;
+    let wallet = _swap_contract_instance._get_wallet();
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+    let alt_token_id = AssetId::from(*_token_contract_id.clone());
+
+    _token_contract_instance.mint_coins(100000).call().await.unwrap();
+    _token_contract_instance
+        .transfer_coins_to_output(100000, _token_contract_id.clone(), wallet.address())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    _swap_contract_instance
+        .initialize(
+            Sender::Address(wallet.address()),
+            [native_asset_id.clone(), _token_contract_id.clone()],
+            1000,
+            300,
+            1000,
+        )
+        .call()
+        .await
+        .unwrap();
+
+    // The alt token accrues yield against the native asset, starting at par
+    let rate_provider_id = Contract::deploy("../mock_rate_provider/out/debug/mock_rate_provider.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let rate_provider_instance = MockRateProvider::new(rate_provider_id.to_string(), wallet.clone());
+    rate_provider_instance.set_rate(1_000_000).call().await.unwrap();
+
+    _swap_contract_instance
+        .set_rate_provider(_token_contract_id.clone(), rate_provider_id.clone())
+        .call()
+        .await
+        .unwrap();
+    let response = _swap_contract_instance
+        .get_rate_provider(_token_contract_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, rate_provider_id);
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // Open the pool at 1:1
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(50000), None))
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(50000), Some(alt_token_id)))
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    _swap_contract_instance
+        .add_liquidity(1, 1000)
+        .set_contracts(&[rate_provider_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+
+    // The oracle can't be swapped out under a funded pool
+    let result = _swap_contract_instance
+        .set_rate_provider(native_asset_id.clone(), rate_provider_id.clone())
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // At par a swap trades about 1:1, less the fee
+    let result = _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(alt_token_id)))
+        .set_contracts(&[rate_provider_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.value < 1000);
+    assert!(result.value > 990);
+
+    // The alt token is now worth 10% more
+    rate_provider_instance.set_rate(1_100_000).call().await.unwrap();
+    let response = _swap_contract_instance
+        .get_rates()
+        .set_contracts(&[rate_provider_id.clone()])
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, [1_000_000, 1_100_000]);
+
+    // Selling it gets about 10% more native assets
+    let result = _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(alt_token_id)))
+        .set_contracts(&[rate_provider_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.value > 1050);
+    assert!(result.value < 1100);
+
+    // And buying it takes about 10% more
+    let result = _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .set_contracts(&[rate_provider_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.value < 950);
+    assert!(result.value > 900);
+
+    // A broken oracle stops trading rather than pricing the asset at zero
+    rate_provider_instance.set_rate(0).call().await.unwrap();
+    let result = _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .set_contracts(&[rate_provider_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_get_virtual_price_at_the_rates() {
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) = get_contract_instance().await;
+    let wallet = _swap_contract_instance._get_wallet();
+
+    let alt_token_id = AssetId::from(*_token_contract_id.clone());
+
+    _token_contract_instance.mint_coins(50000).call().await.unwrap();
+    _token_contract_instance
+        .transfer_coins_to_output(50000, _token_contract_id.clone(), wallet.address())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    _swap_contract_instance
+        .initialize(
+            Sender::Address(wallet.address()),
+            [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()],
+            1000,
+            300,
+            1000,
+        )
+        .call()
+        .await
+        .unwrap();
+
+    let rate_provider_id = Contract::deploy("../mock_rate_provider/out/debug/mock_rate_provider.bin", &wallet, TxParameters::default())
+        .await
+        .unwrap();
+    let rate_provider_instance = MockRateProvider::new(rate_provider_id.to_string(), wallet.clone());
+    rate_provider_instance.set_rate(1_000_000).call().await.unwrap();
+    _swap_contract_instance
+        .set_rate_provider(_token_contract_id.clone(), rate_provider_id.clone())
+        .call()
+        .await
+        .unwrap();
+
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(50000), None))
+        .call()
+        .await
+        .unwrap();
+    _swap_contract_instance
+        .deposit()
+        .call_params(CallParameters::new(Some(50000), Some(alt_token_id)))
+        .call()
+        .await
+        .unwrap();
+    _swap_contract_instance
+        .add_liquidity(1, 1000)
+        .set_contracts(&[rate_provider_id.clone()])
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    let before = _swap_contract_instance
+        .get_virtual_price()
+        .set_contracts(&[rate_provider_id.clone()])
+        .call()
+        .await
+        .unwrap()
+        .value;
+
+    // D is computed from the balances it is given, so doubling the alt token's rate
+    // values the pool at about 1.5 times what it was at par
+    rate_provider_instance.set_rate(2_000_000).call().await.unwrap();
+    let after = _swap_contract_instance
+        .get_virtual_price()
+        .set_contracts(&[rate_provider_id.clone()])
+        .call()
+        .await
+        .unwrap()
+        .value;
+    assert!(after <= before * 3 / 2);
+    assert!(after > before * 149 / 100);
+}
+
+#[tokio::test]
+async fn can_split_fee_with_referrer() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let referrer_wallet = wallets[1].clone();
+
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 200000).await;
+    let _referrer_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), referrer_wallet.clone());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+    let referrer = Sender::Address(referrer_wallet.address());
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+
+    // A referrer can take at most half of the fee
+    let result = _swap_contract_instance
+        .swap_with_referrer(100000, 1, referrer.clone(), 5001, 1000)
+        .call_params(CallParameters::new(Some(100000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    // Nor can it go to the zero address, where nobody could withdraw it
+    let result = _swap_contract_instance
+        .swap_with_referrer(100000, 1, Sender::Address(Address::new([0u8; 32])), 5000, 1000)
+        .call_params(CallParameters::new(Some(100000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    let quote = _swap_contract_instance
+        .get_dy(native_asset_id.clone(), 100000)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    let token_reserve_before = _swap_contract_instance
+        .test_current_reserve(*_token_contract_id)
+        .call()
+        .await
+        .unwrap()
+        .value;
+
+    // The trader gets the same as from a plain swap
+    let result = _swap_contract_instance
+        .swap_with_referrer(100000, 1, referrer.clone(), 5000, 1000)
+        .call_params(CallParameters::new(Some(100000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert_eq!(result.value, quote);
+
+    // Half of the fee is credited to the referrer, the LPs keep the other half
+    let referral_fee = _swap_contract_instance
+        .get_deposit(referrer.clone(), _token_contract_id.clone())
+        .call()
+        .await
+        .unwrap()
+        .value;
+    assert!(referral_fee > 0);
+    let token_reserve_after = _swap_contract_instance
+        .test_current_reserve(*_token_contract_id)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    assert_eq!(token_reserve_before - token_reserve_after, quote + referral_fee);
+
+    // The fee is 0.03% of the gross output
+    let fee = ((quote + referral_fee) * 300) / (1_000_000 - 300);
+    assert!(referral_fee <= fee / 2 + 1);
+    assert!(referral_fee + 1 >= fee / 2);
+
+    // Without a cut the referrer gets nothing
+    _swap_contract_instance
+        .swap_with_referrer(1000, 1, referrer.clone(), 0, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    let response = _swap_contract_instance
+        .get_deposit(referrer, _token_contract_id.clone())
+        .call()
+        .await
+        .unwrap();
+    assert_eq!(response.value, referral_fee);
+
+    // The referrer withdraws it like any deposit
+    _referrer_swap_contract_instance
+        .withdraw(referral_fee, _token_contract_id.clone())
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    let coins = referrer_wallet
+        .get_spendable_coins(&AssetId::from(*_token_contract_id), referral_fee)
+        .await
+        .unwrap();
+    let total_amount: u64 = coins.iter().map(|c| c.amount.0).sum();
+    assert_eq!(total_amount, referral_fee);
+}
+
+#[tokio::test]
+async fn can_track_pool_stats() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+    let alt_token_id = AssetId::from(*_token_contract_id.clone());
+
+    // Adding liquidity is not trading
+    let stats = _swap_contract_instance.get_stats().call().await.unwrap().value;
+    assert_eq!(stats.eth_volume, 0);
+    assert_eq!(stats.token_volume, 0);
+    assert_eq!(stats.swap_count, 0);
+    assert_eq!(stats.last_trade_height, 0);
+
+    _swap_contract_instance
+        .swap(10000, 1, 1000)
+        .call_params(CallParameters::new(Some(10000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    let stats = _swap_contract_instance.get_stats().call().await.unwrap().value;
+    assert_eq!(stats.eth_volume, 10000);
+    assert_eq!(stats.token_volume, 0);
+    assert_eq!(stats.swap_count, 1);
+    // Selling native assets pays the fee in alt tokens
+    assert!(stats.token_fees > 0);
+    assert_eq!(stats.eth_fees, 0);
+    let first_trade_height = stats.last_trade_height;
+    assert!(first_trade_height > 0);
+    let first_token_fees = stats.token_fees;
+
+    _swap_contract_instance
+        .swap(20000, 1, 1000)
+        .call_params(CallParameters::new(Some(20000), Some(alt_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    _swap_contract_instance
+        .swap(5000, 1, 1000)
+        .call_params(CallParameters::new(Some(5000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    let stats = _swap_contract_instance.get_stats().call().await.unwrap().value;
+    assert_eq!(stats.eth_volume, 15000);
+    assert_eq!(stats.token_volume, 20000);
+    assert_eq!(stats.swap_count, 3);
+    assert!(stats.eth_fees > 0);
+    assert!(stats.token_fees > first_token_fees);
+    assert!(stats.last_trade_height > first_trade_height);
+
+    // A failed swap leaves the counters alone
+    let result = _swap_contract_instance
+        .swap(1000, 1_000_000, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+    let response = _swap_contract_instance.get_stats().call().await.unwrap();
+    assert_eq!(response.value, stats);
+}
+
+// TAI64 deadline `seconds` after the node's latest block, converted the way the node labels block times
+async fn tai64_from_latest_block(wallet: &LocalWallet, seconds: i64) -> u64 {
+    let provider = wallet.get_provider().unwrap();
+    let time = provider.client.chain_info().await.unwrap().latest_block.time;
+    Tai64::from_unix(time.timestamp() + seconds).0
+}
+
+#[tokio::test]
+async fn cannot_trade_past_deadlines() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+    let lp_asset_id = AssetId::from(*_swap_contract_id.clone());
+
+    // Height deadline: good for the next few blocks only
+    let current_height = _swap_contract_instance.get_cumulative_prices().call().await.unwrap().value.height;
+    let deadline = current_height + 5;
+    _swap_contract_instance
+        .swap(1000, 1, deadline)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    produce_blocks(&wallet, 5).await;
+    let result = _swap_contract_instance
+        .swap(1000, 1, deadline)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+    let result = _swap_contract_instance
+        .remove_liquidity(1, 1, deadline)
+        .call_params(CallParameters::new(Some(1000), Some(lp_asset_id)))
+        .append_variable_outputs(2)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Timestamp deadline: a time long gone is expired whatever the height
+    let result = _swap_contract_instance
+        .swap(1000, 1, TAI64_OFFSET + 1)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+    let result = _swap_contract_instance
+        .remove_liquidity(1, 1, TAI64_OFFSET + 1)
+        .call_params(CallParameters::new(Some(1000), Some(lp_asset_id)))
+        .append_variable_outputs(2)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // A minute either side of the latest block needs no waiting:
+    // the height deadline above already covers a deadline running out
+    let deadline = tai64_from_latest_block(&wallet, 60).await;
+    _swap_contract_instance
+        .swap(1000, 1, deadline)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    _swap_contract_instance
+        .remove_liquidity(1, 1, deadline)
+        .call_params(CallParameters::new(Some(1000), Some(lp_asset_id)))
+        .append_variable_outputs(2)
+        .call()
+        .await
+        .unwrap();
+
+    let deadline = tai64_from_latest_block(&wallet, -60).await;
+    let result = _swap_contract_instance
+        .swap(1000, 1, deadline)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_trip_circuit_breaker() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    // Only the owner tunes the limits, and not past 100%
+    let result = _other_swap_contract_instance.set_circuit_breaker(100_000, 0).call().await;
+    assert!(result.is_err());
+    let result = _swap_contract_instance.set_circuit_breaker(1_000_001, 0).call().await;
+    assert!(result.is_err());
+
+    // No single trade may take more than 10% of the output reserve
+    _swap_contract_instance.set_circuit_breaker(100_000, 0).call().await.unwrap();
+    let response = _swap_contract_instance.get_circuit_breaker().call().await.unwrap();
+    assert_eq!(response.value.max_trade_ratio, 100_000);
+    assert_eq!(response.value.max_price_deviation, 0);
+
+    let result = _swap_contract_instance
+        .swap(10000, 1, 1000)
+        .call_params(CallParameters::new(Some(10000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+    _swap_contract_instance
+        .swap(4000, 1, 1000)
+        .call_params(CallParameters::new(Some(4000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    // No trade may leave the spot price more than 0.1% away from the EMA
+    _swap_contract_instance.set_circuit_breaker(0, 1000).call().await.unwrap();
+    let result = _swap_contract_instance
+        .swap(40000, 1, 1000)
+        .call_params(CallParameters::new(Some(40000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert!(result.is_err());
+
+    // Small trades near the peg still go through
+    _swap_contract_instance
+        .swap(1000, 1, 1000)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+
+    // Lifting the limits lets the large trade through
+    _swap_contract_instance.set_circuit_breaker(0, 0).call().await.unwrap();
+    _swap_contract_instance
+        .swap(40000, 1, 1000)
+        .call_params(CallParameters::new(Some(40000), Some(native_token_id)))
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn can_commit_and_reveal_swap() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    // Only the hash of the swap goes out with the input
+    let salt = [7u8; 32];
+    let hash = utils::swap_commitment_hash(*native_asset_id, 1000, 1, salt);
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+    _swap_contract_instance
+        .commit_swap(hash)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+
+    let commitment = _swap_contract_instance.get_commitment(Sender::Address(wallet.address()), hash).call().await.unwrap().value;
+    assert_eq!(commitment.owner, *wallet.address());
+    assert_eq!(commitment.asset_id, native_asset_id);
+    assert_eq!(commitment.amount, 1000);
+    assert!(commitment.height > 0);
+
+    // The committed input is held apart from the reserves
+    let response = _swap_contract_instance.get_total_deposits(native_asset_id.clone()).call().await.unwrap();
+    assert_eq!(response.value, 1000);
+
+    // Too early to refund, and only the committer can reveal
+    let result = _swap_contract_instance.refund_swap(hash).append_variable_outputs(1).call().await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+    let result = _other_swap_contract_instance
+        .reveal_swap(native_asset_id.clone(), 1000, 1, salt)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    // Revealed parameters that don't match the hash
+    let result = _swap_contract_instance
+        .reveal_swap(native_asset_id.clone(), 1000, 2, salt)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+    let result = _swap_contract_instance
+        .reveal_swap(native_asset_id.clone(), 1000, 1, [8u8; 32])
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    // The reveal trades like a plain swap
+    let quote = _swap_contract_instance
+        .get_dy(native_asset_id.clone(), 1000)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    let result = _swap_contract_instance
+        .reveal_swap(native_asset_id.clone(), 1000, 1, salt)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert_eq!(result.value, quote);
+
+    let commitment = _swap_contract_instance.get_commitment(Sender::Address(wallet.address()), hash).call().await.unwrap().value;
+    assert_eq!(commitment.height, 0);
+    let response = _swap_contract_instance.get_total_deposits(native_asset_id.clone()).call().await.unwrap();
+    assert_eq!(response.value, 0);
+
+    // A commitment is used once
+    let result = _swap_contract_instance
+        .reveal_swap(native_asset_id.clone(), 1000, 1, salt)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn can_refund_expired_swap_commitment() {
+    let wallet = launch_provider_and_get_wallet().await;
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    let salt = [9u8; 32];
+    let hash = utils::swap_commitment_hash(*native_asset_id, 2000, 1, salt);
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+    _swap_contract_instance
+        .commit_swap(hash)
+        .call_params(CallParameters::new(Some(2000), Some(native_token_id)))
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+
+    // Let the reveal window of 20 blocks pass
+    produce_blocks(&wallet, 21).await;
+    let result = _swap_contract_instance
+        .reveal_swap(native_asset_id.clone(), 2000, 1, salt)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    // The input goes back to the committer, once
+    let response = _swap_contract_instance
+        .refund_swap(hash)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert_eq!(response.value, 2000);
+    let result = _swap_contract_instance.refund_swap(hash).append_variable_outputs(1).call().await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    let response = _swap_contract_instance.get_total_deposits(native_asset_id.clone()).call().await.unwrap();
+    assert_eq!(response.value, 0);
+}
+
+#[tokio::test]
+async fn cannot_squat_swap_commitment() {
+    let wallets = launch_provider_and_get_wallets(2).await;
+    let wallet = wallets[0].clone();
+    let other_wallet = wallets[1].clone();
+
+    let (_swap_contract_instance, _swap_contract_id, _token_contract_instance, _token_contract_id) =
+        get_pool_with_liquidity(&wallet, 50000).await;
+    let _other_swap_contract_instance = MyContract::new(_swap_contract_id.to_string(), other_wallet.clone());
+
+    let native_asset_id = ContractId::new(*NATIVE_ASSET_ID);
+    let native_token_id = AssetId::from(*NATIVE_ASSET_ID);
+
+    // Someone copies a pending hash and commits it first with a single coin
+    let salt = [7u8; 32];
+    let hash = utils::swap_commitment_hash(*native_asset_id, 1000, 1, salt);
+
+    let assets = [ContractId::new(*NATIVE_ASSET_ID), _token_contract_id.clone()];
+    _other_swap_contract_instance
+        .commit_swap(hash)
+        .call_params(CallParameters::new(Some(1), Some(native_token_id)))
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+
+    // The committer's own commitment is unaffected
+    _swap_contract_instance
+        .commit_swap(hash)
+        .call_params(CallParameters::new(Some(1000), Some(native_token_id)))
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    let commitment = _swap_contract_instance
+        .get_commitment(Sender::Address(wallet.address()), hash)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    assert_eq!(commitment.amount, 1000);
+    let commitment = _swap_contract_instance
+        .get_commitment(Sender::Address(other_wallet.address()), hash)
+        .call()
+        .await
+        .unwrap()
+        .value;
+    assert_eq!(commitment.owner, *other_wallet.address());
+    assert_eq!(commitment.amount, 1);
+
+    // The copy can't be revealed, its input doesn't match the hash
+    let result = _other_swap_contract_instance
+        .reveal_swap(native_asset_id.clone(), 1000, 1, salt)
+        .append_variable_outputs(1)
+        .call()
+        .await;
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+    assert!(result.is_err());
+
+    _swap_contract_instance
+        .reveal_swap(native_asset_id.clone(), 1000, 1, salt)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap();
+    assert_solvent(&_swap_contract_instance, &_swap_contract_id, assets.clone()).await;
+}
@@ -0,0 +1,84 @@
+use sha2::{Digest, Sha256};
+
+// Storage delimiter for the deposits ledger, mirrors S_DEPOSITS in the contract
+pub const S_DEPOSITS: [u8; 32] = [0u8; 32];
+
+// Recompute the storage slot the contract uses for an owner's deposits of an asset.
+// Matches `key_deposits` in src/main.sw: sha256((S_DEPOSITS, sha256((owner, asset_id))))
+pub fn key_deposits(owner: [u8; 32], asset_id: [u8; 32]) -> [u8; 32] {
+    let inner = sha256_pair(owner, asset_id);
+    sha256_pair(S_DEPOSITS, inner)
+}
+
+// Hash two b256 words the way Sway hashes a (b256, b256) tuple
+pub fn sha256_pair(a: [u8; 32], b: [u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(a);
+    hasher.update(b);
+    hasher.finalize().into()
+}
+
+// Hash a two-phase swap the way `reveal_swap` does: sha256((asset_in, dx, min_dy, salt)),
+// big-endian words back to back
+pub fn swap_commitment_hash(asset_in: [u8; 32], dx: u64, min_dy: u64, salt: [u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(asset_in);
+    hasher.update(dx.to_be_bytes());
+    hasher.update(min_dy.to_be_bytes());
+    hasher.update(salt);
+    hasher.finalize().into()
+}
+
+// D of two balances, iteration for iteration as `_getD` computes it
+pub fn get_d(xp: [u64; 2], amplification: u64) -> u64 {
+    let n: u64 = 2;
+    let a = amplification * n * n;
+    let s = xp[0] + xp[1];
+
+    let mut d = s;
+    let mut j = 0;
+    for _ in 0..255 {
+        let mut p = d;
+        while j < n {
+            p = (p * d) / (n * xp[j as usize]);
+            j += 1;
+        }
+        let d_prev = d;
+        d = ((a * s + n * p) * d) / ((a - 1) * d + (n + 1) * p);
+        if d.abs_diff(d_prev) <= 1 {
+            break;
+        }
+    }
+
+    d
+}
+
+// Fee over 10^6 between two balances, as `_dynamic_fee` raises it off peg
+pub fn dynamic_fee(xpi: u64, xpj: u64, fee: u64, offpeg_fee_multiplier: u64) -> u64 {
+    let fee_denominator = 1_000_000;
+    if offpeg_fee_multiplier <= fee_denominator || xpi + xpj == 0 {
+        return fee;
+    }
+
+    let share_i = (xpi * fee_denominator) / (xpi + xpj);
+    let share_j = (xpj * fee_denominator) / (xpi + xpj);
+    let balance = (4 * share_i * share_j) / fee_denominator;
+    (offpeg_fee_multiplier * fee) / (((offpeg_fee_multiplier - fee_denominator) * balance) / fee_denominator + fee_denominator)
+}
+
+// Imbalance fees of moving the balances from `old_xp` to `new_xp`, as `imbalance_fees` charges them
+pub fn imbalance_fees(old_xp: [u64; 2], new_xp: [u64; 2], amplification: u64, swap_fee: u64, offpeg_fee_multiplier: u64) -> [u64; 2] {
+    let old_d = get_d(old_xp, amplification);
+    let new_d = get_d(new_xp, amplification);
+    let d_per_coin = (old_d + new_d) / 4;
+    let liquidity_fee = swap_fee / 2;
+
+    let mut fees = [0; 2];
+    for k in 0..2 {
+        let diff = new_xp[k].abs_diff((old_xp[k] * new_d) / old_d);
+        let fee_rate = dynamic_fee((old_xp[k] + new_xp[k]) / 2, d_per_coin, liquidity_fee, offpeg_fee_multiplier);
+        fees[k] = (fee_rate * diff) / 1_000_000;
+    }
+
+    fees
+}
@@ -0,0 +1,120 @@
+use fuel_tx::{AssetId, ContractId, Salt};
+use fuels_abigen_macro::abigen;
+use fuels::prelude::*;
+use fuels::signers::fuel_crypto::SecretKey;
+use fuels::test_helpers::{setup_single_asset_coins, setup_test_provider};
+use std::str::FromStr;
+
+// Load abi from json
+abigen!(StableSwap, "../stable_swap/out/debug/stable_swap-abi.json");
+abigen!(TestToken, "../token_contract/out/debug/token_contract-abi.json");
+
+// Secret key of the address the pools are compiled to accept `initialize` from, see DEPLOYER in stable_swap
+pub const DEPLOYER_SECRET_KEY: &str = "0x0000000000000000000000000000000000000000000000000000000000000001";
+
+// Launch a local network with `num_wallets` wallets holding one coin of 1_000_000 each.
+// The first wallet is the deployer, the others get random keys.
+pub async fn launch_provider_and_get_wallets(num_wallets: u64) -> Vec<LocalWallet> {
+    let mut wallets = vec![LocalWallet::new_from_private_key(SecretKey::from_str(DEPLOYER_SECRET_KEY).unwrap(), None)];
+    for _ in 1..num_wallets {
+        wallets.push(LocalWallet::new_random(None));
+    }
+
+    let coins = wallets
+        .iter()
+        .flat_map(|wallet| setup_single_asset_coins(wallet.address(), AssetId::from(*NATIVE_ASSET_ID), 1, 1_000_000))
+        .collect::<Vec<_>>();
+    let (provider, _) = setup_test_provider(coins, None).await;
+    for wallet in wallets.iter_mut() {
+        wallet.set_provider(provider.clone());
+    }
+
+    wallets
+}
+
+// Launch a local network with the deployer as its only wallet
+pub async fn launch_provider_and_get_wallet() -> LocalWallet {
+    launch_provider_and_get_wallets(1).await.remove(0)
+}
+
+// Deploy another instance of a contract, the salt keeps the contract ids apart
+pub async fn deploy_with_salt(path: &str, wallet: &LocalWallet, salt: u8) -> ContractId {
+    Contract::deploy_with_salt(path, wallet, TxParameters::default(), Salt::from([salt; 32]))
+        .await
+        .unwrap()
+}
+
+// Deploy a token and send `amount` of it to each of the wallets
+pub async fn get_funded_token(wallets: &[LocalWallet], salt: u8, amount: u64) -> ContractId {
+    let token_id = deploy_with_salt("../token_contract/out/debug/token_contract.bin", &wallets[0], salt).await;
+    let token_instance = TestToken::new(token_id.to_string(), wallets[0].clone());
+
+    token_instance.mint_coins(amount * wallets.len() as u64).call().await.unwrap();
+    for wallet in wallets.iter() {
+        token_instance
+            .transfer_coins_to_output(amount, token_id.clone(), wallet.address())
+            .append_variable_outputs(1)
+            .call()
+            .await
+            .unwrap();
+    }
+
+    token_id
+}
+
+// Sum of the wallet's coins of an asset
+pub async fn get_wallet_balance(wallet: &LocalWallet, asset_id: &ContractId) -> u64 {
+    wallet
+        .get_spendable_coins(&AssetId::from(**asset_id), 1)
+        .await
+        .map(|coins| coins.iter().map(|c| c.amount.0).sum())
+        .unwrap_or(0)
+}
+
+// Every transaction is its own block, so this moves the local node forward by `blocks` blocks
+pub async fn produce_blocks(wallet: &LocalWallet, blocks: u64) {
+    for _ in 0..blocks {
+        wallet
+            .transfer(&wallet.address(), 1, AssetId::from(*NATIVE_ASSET_ID), TxParameters::default())
+            .await
+            .unwrap();
+    }
+}
+
+// Deposit `amount` of both assets into the pool and return the LP tokens minted for them
+pub async fn provide_liquidity(wallet: &LocalWallet, pool_id: &ContractId, assets: &[ContractId; 2], amount: u64) -> u64 {
+    let pool_instance = StableSwap::new(pool_id.to_string(), wallet.clone());
+
+    for asset in assets.iter() {
+        pool_instance
+            .deposit()
+            .call_params(CallParameters::new(Some(amount), Some(AssetId::from(**asset))))
+            .call()
+            .await
+            .unwrap();
+    }
+
+    pool_instance
+        .add_liquidity(1, 1000)
+        .append_variable_outputs(1)
+        .call()
+        .await
+        .unwrap()
+        .value
+}
+
+// Deploy a pool for the pair, initialized by and owned by the wallet, which has to be the deployer,
+// and seed it with `liquidity` of both assets
+pub async fn get_pool_with_liquidity(wallet: &LocalWallet, salt: u8, assets: [ContractId; 2], liquidity: u64) -> ContractId {
+    let pool_id = deploy_with_salt("../stable_swap/out/debug/stable_swap.bin", wallet, salt).await;
+    let pool_instance = StableSwap::new(pool_id.to_string(), wallet.clone());
+
+    pool_instance
+        .initialize(Sender::Address(wallet.address()), assets.clone(), 1000, 300, 1000)
+        .call()
+        .await
+        .unwrap();
+    provide_liquidity(wallet, &pool_id, &assets, liquidity).await;
+
+    pool_id
+}